@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE indexer_state (
+    id INTEGER PRIMARY KEY NOT NULL,
+    next_height BIGINT NOT NULL
+);
\ No newline at end of file
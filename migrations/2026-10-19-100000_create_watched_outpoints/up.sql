@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE watched_outpoints (
+    txid TEXT NOT NULL,
+    vout INTEGER NOT NULL,
+    PRIMARY KEY (txid, vout)
+);
\ No newline at end of file
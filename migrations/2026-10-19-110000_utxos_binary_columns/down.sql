@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE utxo_rechecks;
+
+CREATE TABLE utxos_old (
+    txid TEXT NOT NULL,
+    vout INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+    script_pubkey TEXT NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT true,
+    spent BOOLEAN NOT NULL DEFAULT false,
+    spent_by_txid TEXT,
+    block_height INTEGER,
+    PRIMARY KEY (txid, vout)
+);
+
+INSERT INTO utxos_old
+SELECT lower(hex(txid)), vout, value, lower(hex(script_pubkey)), confirmed, spent, nullif(lower(hex(spent_by_txid)), ''), block_height
+FROM utxos;
+
+DROP TABLE utxos;
+ALTER TABLE utxos_old RENAME TO utxos;
@@ -0,0 +1,33 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX mempool_inputs_txid;
+DROP INDEX mempool_tx_seen_at;
+
+CREATE TABLE watched_outpoints_old (
+    txid TEXT NOT NULL,
+    vout INTEGER NOT NULL,
+    PRIMARY KEY (txid, vout)
+);
+INSERT INTO watched_outpoints_old SELECT lower(hex(txid)), vout FROM watched_outpoints;
+DROP TABLE watched_outpoints;
+ALTER TABLE watched_outpoints_old RENAME TO watched_outpoints;
+
+-- The table is rebuilt rather than altered, as `DROP COLUMN` needs SQLite 3.35.
+DROP INDEX utxos_spent_height;
+CREATE TABLE utxos_old (
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    value BIGINT NOT NULL,
+    script_pubkey BLOB NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT true,
+    spent BOOLEAN NOT NULL DEFAULT false,
+    spent_by_txid BLOB,
+    block_height INTEGER,
+    PRIMARY KEY (txid, vout)
+);
+
+INSERT INTO utxos_old
+SELECT txid, vout, value, script_pubkey, confirmed, spent, spent_by_txid, block_height
+FROM utxos;
+
+DROP TABLE utxos;
+ALTER TABLE utxos_old RENAME TO utxos;
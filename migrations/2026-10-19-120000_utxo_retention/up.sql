@@ -0,0 +1,67 @@
+-- Your SQL goes here
+ALTER TABLE utxos ADD COLUMN spent_height INTEGER;
+CREATE INDEX utxos_spent_height ON utxos (spent_height) WHERE spent;
+
+-- Watched txids use the same binary encoding as `utxos` so the pruner can
+-- match them directly.
+CREATE TABLE watched_outpoints_new (
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    PRIMARY KEY (txid, vout)
+);
+
+-- Decoded as in `2026-10-19-110000_utxos_binary_columns`, without `unhex`.
+CREATE TEMP TABLE hex_bytes (
+    hex TEXT PRIMARY KEY NOT NULL,
+    byte BLOB NOT NULL
+);
+INSERT INTO hex_bytes VALUES
+    ('00', x'00'), ('01', x'01'), ('02', x'02'), ('03', x'03'), ('04', x'04'), ('05', x'05'), ('06', x'06'), ('07', x'07'),
+    ('08', x'08'), ('09', x'09'), ('0a', x'0a'), ('0b', x'0b'), ('0c', x'0c'), ('0d', x'0d'), ('0e', x'0e'), ('0f', x'0f'),
+    ('10', x'10'), ('11', x'11'), ('12', x'12'), ('13', x'13'), ('14', x'14'), ('15', x'15'), ('16', x'16'), ('17', x'17'),
+    ('18', x'18'), ('19', x'19'), ('1a', x'1a'), ('1b', x'1b'), ('1c', x'1c'), ('1d', x'1d'), ('1e', x'1e'), ('1f', x'1f'),
+    ('20', x'20'), ('21', x'21'), ('22', x'22'), ('23', x'23'), ('24', x'24'), ('25', x'25'), ('26', x'26'), ('27', x'27'),
+    ('28', x'28'), ('29', x'29'), ('2a', x'2a'), ('2b', x'2b'), ('2c', x'2c'), ('2d', x'2d'), ('2e', x'2e'), ('2f', x'2f'),
+    ('30', x'30'), ('31', x'31'), ('32', x'32'), ('33', x'33'), ('34', x'34'), ('35', x'35'), ('36', x'36'), ('37', x'37'),
+    ('38', x'38'), ('39', x'39'), ('3a', x'3a'), ('3b', x'3b'), ('3c', x'3c'), ('3d', x'3d'), ('3e', x'3e'), ('3f', x'3f'),
+    ('40', x'40'), ('41', x'41'), ('42', x'42'), ('43', x'43'), ('44', x'44'), ('45', x'45'), ('46', x'46'), ('47', x'47'),
+    ('48', x'48'), ('49', x'49'), ('4a', x'4a'), ('4b', x'4b'), ('4c', x'4c'), ('4d', x'4d'), ('4e', x'4e'), ('4f', x'4f'),
+    ('50', x'50'), ('51', x'51'), ('52', x'52'), ('53', x'53'), ('54', x'54'), ('55', x'55'), ('56', x'56'), ('57', x'57'),
+    ('58', x'58'), ('59', x'59'), ('5a', x'5a'), ('5b', x'5b'), ('5c', x'5c'), ('5d', x'5d'), ('5e', x'5e'), ('5f', x'5f'),
+    ('60', x'60'), ('61', x'61'), ('62', x'62'), ('63', x'63'), ('64', x'64'), ('65', x'65'), ('66', x'66'), ('67', x'67'),
+    ('68', x'68'), ('69', x'69'), ('6a', x'6a'), ('6b', x'6b'), ('6c', x'6c'), ('6d', x'6d'), ('6e', x'6e'), ('6f', x'6f'),
+    ('70', x'70'), ('71', x'71'), ('72', x'72'), ('73', x'73'), ('74', x'74'), ('75', x'75'), ('76', x'76'), ('77', x'77'),
+    ('78', x'78'), ('79', x'79'), ('7a', x'7a'), ('7b', x'7b'), ('7c', x'7c'), ('7d', x'7d'), ('7e', x'7e'), ('7f', x'7f'),
+    ('80', x'80'), ('81', x'81'), ('82', x'82'), ('83', x'83'), ('84', x'84'), ('85', x'85'), ('86', x'86'), ('87', x'87'),
+    ('88', x'88'), ('89', x'89'), ('8a', x'8a'), ('8b', x'8b'), ('8c', x'8c'), ('8d', x'8d'), ('8e', x'8e'), ('8f', x'8f'),
+    ('90', x'90'), ('91', x'91'), ('92', x'92'), ('93', x'93'), ('94', x'94'), ('95', x'95'), ('96', x'96'), ('97', x'97'),
+    ('98', x'98'), ('99', x'99'), ('9a', x'9a'), ('9b', x'9b'), ('9c', x'9c'), ('9d', x'9d'), ('9e', x'9e'), ('9f', x'9f'),
+    ('a0', x'a0'), ('a1', x'a1'), ('a2', x'a2'), ('a3', x'a3'), ('a4', x'a4'), ('a5', x'a5'), ('a6', x'a6'), ('a7', x'a7'),
+    ('a8', x'a8'), ('a9', x'a9'), ('aa', x'aa'), ('ab', x'ab'), ('ac', x'ac'), ('ad', x'ad'), ('ae', x'ae'), ('af', x'af'),
+    ('b0', x'b0'), ('b1', x'b1'), ('b2', x'b2'), ('b3', x'b3'), ('b4', x'b4'), ('b5', x'b5'), ('b6', x'b6'), ('b7', x'b7'),
+    ('b8', x'b8'), ('b9', x'b9'), ('ba', x'ba'), ('bb', x'bb'), ('bc', x'bc'), ('bd', x'bd'), ('be', x'be'), ('bf', x'bf'),
+    ('c0', x'c0'), ('c1', x'c1'), ('c2', x'c2'), ('c3', x'c3'), ('c4', x'c4'), ('c5', x'c5'), ('c6', x'c6'), ('c7', x'c7'),
+    ('c8', x'c8'), ('c9', x'c9'), ('ca', x'ca'), ('cb', x'cb'), ('cc', x'cc'), ('cd', x'cd'), ('ce', x'ce'), ('cf', x'cf'),
+    ('d0', x'd0'), ('d1', x'd1'), ('d2', x'd2'), ('d3', x'd3'), ('d4', x'd4'), ('d5', x'd5'), ('d6', x'd6'), ('d7', x'd7'),
+    ('d8', x'd8'), ('d9', x'd9'), ('da', x'da'), ('db', x'db'), ('dc', x'dc'), ('dd', x'dd'), ('de', x'de'), ('df', x'df'),
+    ('e0', x'e0'), ('e1', x'e1'), ('e2', x'e2'), ('e3', x'e3'), ('e4', x'e4'), ('e5', x'e5'), ('e6', x'e6'), ('e7', x'e7'),
+    ('e8', x'e8'), ('e9', x'e9'), ('ea', x'ea'), ('eb', x'eb'), ('ec', x'ec'), ('ed', x'ed'), ('ee', x'ee'), ('ef', x'ef'),
+    ('f0', x'f0'), ('f1', x'f1'), ('f2', x'f2'), ('f3', x'f3'), ('f4', x'f4'), ('f5', x'f5'), ('f6', x'f6'), ('f7', x'f7'),
+    ('f8', x'f8'), ('f9', x'f9'), ('fa', x'fa'), ('fb', x'fb'), ('fc', x'fc'), ('fd', x'fd'), ('fe', x'fe'), ('ff', x'ff');
+
+INSERT INTO watched_outpoints_new
+SELECT
+    (WITH RECURSIVE step(rest, bytes) AS (
+        SELECT lower(watched_outpoints.txid), CAST('' AS BLOB)
+        UNION ALL
+        SELECT substr(rest, 3), bytes || byte FROM step JOIN hex_bytes ON hex = substr(rest, 1, 2)
+    ) SELECT CAST(bytes AS BLOB) FROM step WHERE rest = ''),
+    vout
+FROM watched_outpoints;
+
+DROP TABLE hex_bytes;
+DROP TABLE watched_outpoints;
+ALTER TABLE watched_outpoints_new RENAME TO watched_outpoints;
+
+CREATE INDEX mempool_tx_seen_at ON mempool_tx (seen_at);
+CREATE INDEX mempool_inputs_txid ON mempool_inputs (txid);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE fidelity_bonds (
+    onion_address TEXT PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    amount BIGINT NOT NULL,
+    lock_time BIGINT NOT NULL,
+    pubkey BLOB NOT NULL,
+    conf_height INTEGER,
+    cert_expiry INTEGER
+);
+
+CREATE INDEX fidelity_bonds_outpoint ON fidelity_bonds (txid, vout);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE servers ADD COLUMN state TEXT NOT NULL DEFAULT 'active';
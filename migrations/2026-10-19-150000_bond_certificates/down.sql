@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+-- The table is rebuilt rather than altered, as `DROP COLUMN` needs SQLite 3.35.
+CREATE TABLE fidelity_bonds_old (
+    onion_address TEXT PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    amount BIGINT NOT NULL,
+    lock_time BIGINT NOT NULL,
+    pubkey BLOB NOT NULL,
+    conf_height INTEGER,
+    cert_expiry INTEGER
+);
+
+INSERT INTO fidelity_bonds_old
+SELECT onion_address, txid, vout, amount, lock_time, pubkey, conf_height, cert_expiry
+FROM fidelity_bonds;
+
+DROP TABLE fidelity_bonds;
+ALTER TABLE fidelity_bonds_old RENAME TO fidelity_bonds;
+
+CREATE INDEX fidelity_bonds_outpoint ON fidelity_bonds (txid, vout);
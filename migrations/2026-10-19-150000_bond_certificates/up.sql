@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE fidelity_bonds ADD COLUMN cert_hash BLOB;
+ALTER TABLE fidelity_bonds ADD COLUMN cert_sig BLOB;
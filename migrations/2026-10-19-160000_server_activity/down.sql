@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+-- The table is rebuilt rather than altered, as `DROP COLUMN` needs SQLite 3.35.
+CREATE TABLE servers_old (
+    onion_address TEXT PRIMARY KEY,
+    cooldown_seconds REAL NOT NULL,
+    stale BOOLEAN NOT NULL,
+    state TEXT NOT NULL DEFAULT 'active'
+);
+
+INSERT INTO servers_old
+SELECT onion_address, cooldown_seconds, stale, state
+FROM servers;
+
+DROP TABLE servers;
+ALTER TABLE servers_old RENAME TO servers;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE servers ADD COLUMN first_seen TIMESTAMP;
+ALTER TABLE servers ADD COLUMN last_seen TIMESTAMP;
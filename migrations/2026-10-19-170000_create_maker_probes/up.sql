@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE maker_probes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    onion_address TEXT NOT NULL,
+    probed_at TIMESTAMP NOT NULL,
+    success BOOLEAN NOT NULL,
+    latency_ms BIGINT,
+    error_kind TEXT
+);
+
+CREATE INDEX maker_probes_address_time ON maker_probes (onion_address, probed_at);
+CREATE INDEX maker_probes_time ON maker_probes (probed_at);
@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE maker_transitions;
+
+CREATE TABLE servers_old (
+    onion_address TEXT PRIMARY KEY,
+    cooldown_seconds REAL NOT NULL,
+    stale BOOLEAN NOT NULL,
+    state TEXT NOT NULL DEFAULT 'active',
+    first_seen TIMESTAMP,
+    last_seen TIMESTAMP
+);
+
+INSERT INTO servers_old
+SELECT onion_address, cooldown_seconds, state IN ('stale', 'evicted', 'banned'),
+    CASE WHEN state IN ('pending', 'degraded', 'stale', 'evicted', 'banned') THEN 'active' ELSE state END,
+    first_seen, last_seen
+FROM servers;
+
+DROP TABLE servers;
+ALTER TABLE servers_old RENAME TO servers;
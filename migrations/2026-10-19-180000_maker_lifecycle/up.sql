@@ -0,0 +1,28 @@
+-- Your SQL goes here
+-- The table is rebuilt rather than altered, as `DROP COLUMN` needs SQLite 3.35.
+CREATE TABLE servers_new (
+    onion_address TEXT PRIMARY KEY,
+    cooldown_seconds REAL NOT NULL,
+    state TEXT NOT NULL DEFAULT 'active',
+    first_seen TIMESTAMP,
+    last_seen TIMESTAMP,
+    failures INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO servers_new (onion_address, cooldown_seconds, state, first_seen, last_seen)
+SELECT onion_address, cooldown_seconds, CASE WHEN stale AND state = 'active' THEN 'stale' ELSE state END, first_seen, last_seen
+FROM servers;
+
+DROP TABLE servers;
+ALTER TABLE servers_new RENAME TO servers;
+
+CREATE TABLE maker_transitions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    onion_address TEXT NOT NULL,
+    from_state TEXT,
+    to_state TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX maker_transitions_address_time ON maker_transitions (onion_address, at);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE maker_offers (
+    onion_address TEXT PRIMARY KEY NOT NULL,
+    base_fee BIGINT NOT NULL,
+    amount_relative_fee_pct DOUBLE NOT NULL,
+    time_relative_fee_pct DOUBLE NOT NULL,
+    required_confirms INTEGER NOT NULL,
+    minimum_locktime INTEGER NOT NULL,
+    min_size BIGINT NOT NULL,
+    max_size BIGINT NOT NULL,
+    fetched_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE maker_versions (
+    onion_address TEXT PRIMARY KEY NOT NULL,
+    software_version TEXT NOT NULL,
+    protocol_versions TEXT NOT NULL,
+    reported_at TIMESTAMP NOT NULL
+);
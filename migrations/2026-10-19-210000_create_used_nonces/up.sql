@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE used_nonces (
+    nonce BIGINT PRIMARY KEY NOT NULL,
+    used_at TIMESTAMP NOT NULL
+);
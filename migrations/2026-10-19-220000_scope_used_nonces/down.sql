@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE used_nonces;
+
+CREATE TABLE used_nonces (
+    nonce BIGINT PRIMARY KEY NOT NULL,
+    used_at TIMESTAMP NOT NULL
+);
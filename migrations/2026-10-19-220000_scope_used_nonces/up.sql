@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- Nonces are scoped to the bond that signed the request, so a request for one
+-- bond cannot use up a nonce another maker is about to send. Nonces stored so
+-- far cannot be attributed to a bond and are dropped.
+DROP TABLE used_nonces;
+
+CREATE TABLE used_nonces (
+    bond_txid BLOB NOT NULL,
+    bond_vout INTEGER NOT NULL,
+    nonce BIGINT NOT NULL,
+    used_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (bond_txid, bond_vout, nonce)
+);
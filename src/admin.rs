@@ -0,0 +1,311 @@
+//! Minimal HTTP interface for operators. It has no authentication, so it
+//! should only be bound to a loopback or otherwise private address.
+//!
+//! - `GET /metrics`: counters and gauges in the Prometheus text format.
+//! - `GET /sybil`: recent bond conflicts as JSON.
+//! - `GET /probes`: uptime and latency rollups per maker as JSON.
+//! - `POST /makers/<address>/ban` and `POST /makers/<address>/unban`: keep a
+//!   maker out of the listing, or let it back in.
+//! - `POST /policy/reload`: re-reads the listing policy file and returns the
+//!   policy now in force.
+
+use std::{collections::BTreeMap, path::Path};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+};
+use tracing::{error, info, warn};
+
+use crate::{db::policy::ListingPolicy, error::TrackerError, metrics::METRICS, types::DbRequest};
+
+/// Upper bound on the request size, to keep a misbehaving client
+/// from growing our buffers.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    fn json(body: &impl serde::Serialize) -> Self {
+        match serde_json::to_string_pretty(body) {
+            Ok(body) => Self {
+                status: "200 OK",
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::error("500 Internal Server Error", e.to_string()),
+        }
+    }
+
+    fn error(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body,
+        }
+    }
+}
+
+pub async fn run(db_tx: Sender<DbRequest>, address: String, policy_file: Option<String>) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind admin interface to {address}: {e}");
+            return;
+        }
+    };
+    info!("Admin interface listening on {}", address);
+
+    while let Ok((stream, _)) = listener.accept().await {
+        let db_tx = db_tx.clone();
+        let policy_file = policy_file.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, db_tx, policy_file.as_deref()).await {
+                warn!("Admin request failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    db_tx: Sender<DbRequest>,
+    policy_file: Option<&str>,
+) -> Result<(), TrackerError> {
+    let response = match read_request(&mut stream).await? {
+        Some(request) => route(request, &db_tx, policy_file).await,
+        None => Response::error("400 Bad Request", "malformed request".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn route(request: Request, db_tx: &Sender<DbRequest>, policy_file: Option<&str>) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => {
+            let Some(servers) = query(db_tx, DbRequest::QueryAll).await else {
+                return unavailable();
+            };
+            let Some(probes) = query(db_tx, DbRequest::QueryProbeStats).await else {
+                return unavailable();
+            };
+            let Some(versions) = query(db_tx, DbRequest::QueryVersions).await else {
+                return unavailable();
+            };
+            Response::text(METRICS.render(&servers, &probes, &versions))
+        }
+        ("GET", "/probes") => match query(db_tx, DbRequest::QueryProbeStats).await {
+            Some(probes) => Response::json(&probes.into_iter().collect::<BTreeMap<_, _>>()),
+            None => unavailable(),
+        },
+        ("GET", "/sybil") => match query(db_tx, DbRequest::QueryConflicts).await {
+            Some(conflicts) => Response::json(&conflicts),
+            None => unavailable(),
+        },
+        ("POST", "/policy/reload") => {
+            let Some(path) = policy_file else {
+                return Response::error("400 Bad Request", "no policy file configured".to_string());
+            };
+            let policy = match ListingPolicy::load(Path::new(path)) {
+                Ok(policy) => policy,
+                Err(e) => return Response::error("400 Bad Request", e.to_string()),
+            };
+            if db_tx
+                .send(DbRequest::SetPolicy(policy.clone()))
+                .await
+                .is_err()
+            {
+                return unavailable();
+            }
+            Response::json(&policy)
+        }
+        ("POST", path) if path.starts_with("/makers/") => {
+            let Some((address, banned)) = path
+                .strip_prefix("/makers/")
+                .and_then(|rest| rest.rsplit_once('/'))
+                .and_then(|(address, action)| match action {
+                    "ban" => Some((address, true)),
+                    "unban" => Some((address, false)),
+                    _ => None,
+                })
+            else {
+                return Response::error("404 Not Found", "unknown endpoint".to_string());
+            };
+            let address = address.to_string();
+            let request = |resp_tx| DbRequest::Ban {
+                address,
+                banned,
+                resp_tx,
+            };
+            match query(db_tx, request).await {
+                Some(true) => {
+                    Response::text(if banned { "banned" } else { "unbanned" }.to_string())
+                }
+                Some(false) => Response::error("404 Not Found", "unknown maker".to_string()),
+                None => unavailable(),
+            }
+        }
+        _ => Response::error("404 Not Found", "unknown endpoint".to_string()),
+    }
+}
+
+/// Sends the request built by `request` and waits for the db manager's answer.
+async fn query<T>(
+    db_tx: &Sender<DbRequest>,
+    request: impl FnOnce(mpsc::Sender<T>) -> DbRequest,
+) -> Option<T> {
+    let (resp_tx, mut resp_rx) = mpsc::channel(1);
+    db_tx.send(request(resp_tx)).await.ok()?;
+    resp_rx.recv().await
+}
+
+fn unavailable() -> Response {
+    Response::error(
+        "503 Service Unavailable",
+        "db manager unavailable".to_string(),
+    )
+}
+
+/// Reads the request line and skips the headers. Returns `None` for requests
+/// we cannot parse.
+async fn read_request(stream: &mut TcpStream) -> Result<Option<Request>, TrackerError> {
+    let mut reader = BufReader::new(stream).take(MAX_REQUEST_SIZE as u64);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+    }
+    Ok(Some(Request { method, path }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{server, version};
+    use crate::types::{MakerState, ServerInfo};
+    use std::collections::HashMap;
+
+    async fn get(address: &str, path: &str) -> String {
+        request(address, "GET", path).await
+    }
+
+    async fn request(address: &str, method: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics_and_conflicts() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let (db_tx, mut db_rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            while let Some(request) = db_rx.recv().await {
+                match request {
+                    DbRequest::QueryAll(resp_tx) => {
+                        let evicted = ServerInfo {
+                            state: MakerState::Evicted,
+                            ..server("b.onion:6102", None)
+                        };
+                        let servers = vec![
+                            ("a.onion:6102".to_string(), server("a.onion:6102", None)),
+                            ("b.onion:6102".to_string(), evicted),
+                        ];
+                        resp_tx.send(servers).await.unwrap()
+                    }
+                    DbRequest::QueryProbeStats(resp_tx) => {
+                        resp_tx.send(HashMap::new()).await.unwrap()
+                    }
+                    DbRequest::QueryVersions(resp_tx) => {
+                        // Only the listed maker counts towards the version gauges.
+                        let versions = HashMap::from([
+                            (
+                                "a.onion:6102".to_string(),
+                                version("coinswap/\"0.2\"", &[1, 2]),
+                            ),
+                            (
+                                "b.onion:6102".to_string(),
+                                version("coinswap/\"0.2\"", &[2]),
+                            ),
+                        ]);
+                        resp_tx.send(versions).await.unwrap()
+                    }
+                    DbRequest::QueryConflicts(resp_tx) => resp_tx.send(Vec::new()).await.unwrap(),
+                    DbRequest::Ban {
+                        address, resp_tx, ..
+                    } => resp_tx.send(address == "a.onion:6102").await.unwrap(),
+                    _ => unreachable!(),
+                }
+            }
+        });
+        tokio::spawn(run(db_tx, address.clone(), None));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let metrics = get(&address, "/metrics").await;
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        assert!(metrics.contains("tracker_sybil_conflicts_total "));
+        assert!(metrics.contains("tracker_probes_total{result=\"failure\"} "));
+        assert!(
+            metrics.contains("tracker_maker_software_versions{version=\"coinswap/\\\"0.2\\\"\"} 1")
+        );
+        assert!(metrics.contains("tracker_maker_protocol_versions{protocol=\"2\"} 1"));
+        let probes = get(&address, "/probes").await;
+        assert!(probes.starts_with("HTTP/1.1 200 OK"));
+        assert!(probes.ends_with("{}"));
+        let sybil = get(&address, "/sybil").await;
+        assert!(sybil.starts_with("HTTP/1.1 200 OK"));
+        assert!(sybil.ends_with("[]"));
+        assert!(get(&address, "/nope").await.starts_with("HTTP/1.1 404"));
+
+        let ban = request(&address, "POST", "/makers/a.onion:6102/ban").await;
+        assert!(ban.starts_with("HTTP/1.1 200 OK"));
+        assert!(ban.ends_with("banned"));
+        let unknown = request(&address, "POST", "/makers/b.onion:6102/unban").await;
+        assert!(unknown.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -1,32 +1,191 @@
-use crate::db::model::MempoolTx;
-use diesel::RunQueryDsl;
+use crate::db::model::{Bond, MempoolTx, Server, WatchedOutpoint};
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::{Connection, RunQueryDsl};
 use diesel::{ExpressionMethods, JoinOnDsl, QueryDsl, SqliteConnection, r2d2::ConnectionManager};
 use r2d2::Pool;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::mpsc::Receiver;
-use tracing::info;
+use rand::{SeedableRng, rngs::StdRng};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc::Receiver},
+    time::Instant,
+};
+use tracing::{error, info, warn};
 
 use crate::{
-    db::schema::{mempool_inputs, mempool_tx},
+    db::{
+        lifecycle::{Lifecycle, transition},
+        nonces, offers,
+        policy::{ListingPolicy, Tip},
+        probes::{self, ProbeStats},
+        sampling::{bond_score, sample},
+        schema::{fidelity_bonds, mempool_inputs, mempool_tx, servers, watched_outpoints},
+        versions,
+    },
     error::TrackerError,
+    indexer::{verify_certificate, verify_depth},
+    metrics::METRICS,
     status::{self, Status},
-    types::{DbRequest, ServerInfo},
+    types::{
+        BondConflict, BondRecord, DbRequest, FidelityBond, MakerRecord, MakerState, MakerVersion,
+        OfferRecord, RegistryEvent, ServerInfo,
+    },
 };
 
+/// Conflicts kept for the admin interface.
+const MAX_CONFLICTS: usize = 256;
+
+/// Which address keeps a bond when several register with the same bond
+/// outpoint or bond pubkey.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SybilPolicy {
+    /// The newest registration is active and older ones become duplicates.
+    #[default]
+    LatestWins,
+    /// Later registrations are refused while the first one is active.
+    FirstWins,
+}
+
+/// Rules the DB manager applies to registrations.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegistrationPolicy {
+    pub sybil: SybilPolicy,
+    /// Confirmations a bond must have, checked on `Post`. Bonded makers below
+    /// it are not listed until their bond is deep enough.
+    pub min_bond_confirmations: u32,
+}
+
+impl FromStr for SybilPolicy {
+    type Err = TrackerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latest-wins" => Ok(SybilPolicy::LatestWins),
+            "first-wins" => Ok(SybilPolicy::FirstWins),
+            _ => Err(TrackerError::General(format!("Unknown sybil policy: {s}"))),
+        }
+    }
+}
+
+/// How long nonces of signed maker requests are remembered. Must exceed the
+/// window in which the server accepts a request timestamp.
+const NONCE_RETENTION: chrono::Duration = chrono::Duration::minutes(30);
+
 pub async fn run(
     pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
     mut rx: Receiver<DbRequest>,
     status_tx: status::Sender,
+    registration: RegistrationPolicy,
+    mut listing_policy: ListingPolicy,
+    lifecycle: Lifecycle,
+    events: broadcast::Sender<RegistryEvent>,
 ) {
-    let mut servers: HashMap<String, ServerInfo> = HashMap::new();
     let mut conn = pool.get().unwrap();
-    info!("DB manager started");
+    let loaded = load_servers(&mut conn).and_then(|servers| {
+        let since = Utc::now() - NONCE_RETENTION;
+        Ok((servers, nonces::load(&mut conn, since)?))
+    });
+    let (mut servers, mut used_nonces) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Failed to load servers: {e:?}");
+            let _ = status_tx
+                .send(Status {
+                    state: status::State::DBShutdown(e),
+                })
+                .await;
+            return;
+        }
+    };
+    info!("DB manager started with {} known servers", servers.len());
+    let mut conflicts: VecDeque<BondConflict> = VecDeque::new();
+    let sybil_policy = registration.sybil;
+    for conflict in resolve_loaded_conflicts(&mut conn, &mut servers, &events, sybil_policy) {
+        warn!("Bond conflict among stored makers: {conflict:?}");
+        if conflicts.len() == MAX_CONFLICTS {
+            conflicts.pop_front();
+        }
+        conflicts.push_back(conflict);
+    }
+    let mut tip: Option<Tip> = None;
+    let mut rng = StdRng::from_entropy();
     while let Some(request) = rx.recv().await {
         match request {
             DbRequest::Add(addr, info) => {
                 info!("Add request intercepted: address: {addr:?}, info: {info:?}");
+                let known = servers.get(&addr);
+                if known.is_some_and(|known| known.state == MakerState::Banned) {
+                    warn!("Ignoring registration of banned maker {addr}");
+                    continue;
+                }
+                if known.is_some_and(|known| downgrades_bond(&info, known)) {
+                    warn!("Ignoring registration of {addr} with a smaller bond than its own");
+                    continue;
+                }
+                let found =
+                    resolve_conflicts(&mut conn, &mut servers, &events, sybil_policy, &addr, &info);
+                let refused = found.iter().any(|conflict| conflict.rejected == addr);
+                METRICS
+                    .sybil_conflicts
+                    .fetch_add(found.len() as u64, Ordering::Relaxed);
+                for conflict in found {
+                    warn!("Bond conflict: {conflict:?}");
+                    if conflicts.len() == MAX_CONFLICTS {
+                        conflicts.pop_front();
+                    }
+                    conflicts.push_back(conflict);
+                }
+                if refused {
+                    continue;
+                }
+                // A live maker registering again keeps its liveness; any
+                // other one starts over.
+                let mut info = info;
+                let registered = info.state;
+                let previous = servers.get(&addr).map(|known| {
+                    keep_certificate(&mut info, known);
+                    info.first_seen = known.first_seen;
+                    info.last_seen = known.last_seen;
+                    if known.state.is_probed() {
+                        info.state = known.state;
+                        info.failures = known.failures;
+                    }
+                    known.state
+                });
+                if previous.is_none_or(|previous| !previous.is_probed()) {
+                    transition(
+                        &mut conn,
+                        &addr,
+                        previous,
+                        &mut info,
+                        registered,
+                        "registered",
+                    );
+                }
+                persist_server(&mut conn, &addr, &info);
+                let event = match previous {
+                    Some(_) => RegistryEvent::Updated(addr.clone(), info.clone()),
+                    None => RegistryEvent::Added(addr.clone(), info.clone()),
+                };
+                let _ = events.send(event);
                 servers.insert(addr, info);
             }
+            DbRequest::Sync(resp_tx) => {
+                let _ = resp_tx.send(()).await;
+            }
+            DbRequest::SetPolicy(policy) => {
+                info!("Listing policy updated: {policy:?}");
+                listing_policy = policy;
+            }
+            DbRequest::QueryConflicts(resp_tx) => {
+                info!("Query conflicts intercepted");
+                let _ = resp_tx.send(conflicts.iter().cloned().collect()).await;
+            }
             DbRequest::Query(addr, resp_tx) => {
                 info!("Query request intecepted");
                 let result = servers.get(&addr).cloned();
@@ -34,26 +193,199 @@ pub async fn run(
             }
             DbRequest::Update(addr, server_info) => {
                 info!("Update request intercepted");
+                persist_server(&mut conn, &addr, &server_info);
+                let _ = events.send(RegistryEvent::Updated(addr.clone(), server_info.clone()));
                 servers.insert(addr, server_info);
             }
+            DbRequest::RecordProbe { address, outcome } => {
+                info!("Probe result intercepted: {address}: {outcome:?}");
+                let Some(info) = servers.get_mut(&address) else {
+                    continue;
+                };
+                let now = Utc::now();
+                if let Err(e) = probes::record(&mut conn, &address, now, &outcome) {
+                    error!("Failed to record probe of {address}: {e}");
+                }
+                if outcome.is_ok() {
+                    METRICS.probe_successes.fetch_add(1, Ordering::Relaxed);
+                    mark_seen(info, now);
+                } else {
+                    METRICS.probe_failures.fetch_add(1, Ordering::Relaxed);
+                    info.failures = info.failures.saturating_add(1);
+                    info.up_since = None;
+                }
+                let silent_for = (now - info.last_seen.unwrap_or(info.first_seen))
+                    .to_std()
+                    .unwrap_or_default();
+                let next = lifecycle.next_state(info.state, info.failures, silent_for);
+                if next != info.state {
+                    let reason = match &outcome {
+                        Ok(_) => "answered probe".to_string(),
+                        Err(kind) => {
+                            format!("{} failed probes in a row, last: {kind}", info.failures)
+                        }
+                    };
+                    transition(&mut conn, &address, Some(info.state), info, next, &reason);
+                    let _ = events.send(RegistryEvent::Updated(address.clone(), info.clone()));
+                }
+                persist_server(&mut conn, &address, info);
+            }
+            DbRequest::StoreOffer { address, offer } => {
+                info!("Offer intercepted: {address}: {offer:?}");
+                if !servers.contains_key(&address) {
+                    continue;
+                }
+                if let Err(e) = offers::store(&mut conn, &address, &offer, Utc::now()) {
+                    error!("Failed to store offer of {address}: {e}");
+                }
+            }
+            DbRequest::StoreVersion { address, version } => {
+                info!("Version intercepted: {address}: {version:?}");
+                if !servers.contains_key(&address) {
+                    continue;
+                }
+                if let Err(e) = versions::store(&mut conn, &address, &version, Utc::now()) {
+                    error!("Failed to store version of {address}: {e}");
+                }
+            }
+            DbRequest::QueryVersions(resp_tx) => {
+                info!("Query versions intercepted");
+                let _ = resp_tx.send(maker_versions(&mut conn)).await;
+            }
+            DbRequest::Ban {
+                address,
+                banned,
+                resp_tx,
+            } => {
+                info!("Ban intercepted: {address}: {banned}");
+                let Some(info) = servers.get_mut(&address) else {
+                    let _ = resp_tx.send(false).await;
+                    continue;
+                };
+                let (to, reason) = match banned {
+                    true => (MakerState::Banned, "banned by operator"),
+                    false => (MakerState::Pending, "unbanned by operator"),
+                };
+                if (info.state == MakerState::Banned) != banned {
+                    info.failures = 0;
+                    transition(&mut conn, &address, Some(info.state), info, to, reason);
+                    persist_server(&mut conn, &address, info);
+                    let _ = events.send(RegistryEvent::Updated(address.clone(), info.clone()));
+                }
+                let _ = resp_tx.send(true).await;
+            }
             DbRequest::QueryAll(resp_tx) => {
                 info!("Query all request intercepted");
                 let response: Vec<(String, ServerInfo)> =
                     servers.iter().map(|e| (e.0.clone(), e.1.clone())).collect();
                 let _ = resp_tx.send(response).await;
             }
-            DbRequest::QueryActive(resp_tx) => {
-                info!("Query active intercepted");
-                let response: Vec<String> = servers
-                    .iter()
-                    .filter(|x| !x.1.stale)
-                    .map(|e| e.0.clone())
-                    .collect();
+            DbRequest::QueryActive(protocol, resp_tx) => {
+                info!("Query active intercepted: protocol: {protocol:?}");
+                let reported = match protocol {
+                    Some(_) => maker_versions(&mut conn),
+                    None => HashMap::new(),
+                };
+                let response: Vec<String> = listed(
+                    &servers,
+                    &listing_policy,
+                    registration.min_bond_confirmations,
+                    tip,
+                )
+                .filter(|(address, _)| {
+                    protocol.is_none_or(|protocol| {
+                        reported
+                            .get(*address)
+                            .is_some_and(|version| version.supports(protocol))
+                    })
+                })
+                .map(|e| e.0.clone())
+                .collect();
+                let _ = resp_tx.send(response).await;
+            }
+            DbRequest::QueryRecords(resp_tx) => {
+                info!("Query records intercepted");
+                let stats = probe_stats(&mut conn);
+                let reported = maker_versions(&mut conn);
+                let response: Vec<MakerRecord> = listed(
+                    &servers,
+                    &listing_policy,
+                    registration.min_bond_confirmations,
+                    tip,
+                )
+                .map(|(address, info)| {
+                    let (stats, version) = (stats.get(address), reported.get(address));
+                    maker_record(address, info, tip, stats, version)
+                })
+                .collect();
+                let _ = resp_tx.send(response).await;
+            }
+            DbRequest::QueryOffers(filter, resp_tx) => {
+                info!("Query offers intercepted: {filter:?}");
+                let mut cached = offers::load(&mut conn).unwrap_or_else(|e| {
+                    error!("Failed to load offers: {e}");
+                    HashMap::new()
+                });
+                let response: Vec<OfferRecord> = listed(
+                    &servers,
+                    &listing_policy,
+                    registration.min_bond_confirmations,
+                    tip,
+                )
+                .filter_map(|(address, _)| {
+                    let (offer, fetched_at) = cached.remove(address)?;
+                    filter.matches(&offer).then(|| OfferRecord {
+                        address: address.clone(),
+                        offer,
+                        fetched_at,
+                    })
+                })
+                .collect();
+                let _ = resp_tx.send(response).await;
+            }
+            DbRequest::QueryProbeStats(resp_tx) => {
+                info!("Query probe stats intercepted");
+                let _ = resp_tx.send(probe_stats(&mut conn)).await;
+            }
+            DbRequest::QuerySample {
+                count,
+                min_bond,
+                seed,
+                resp_tx,
+            } => {
+                info!("Query sample intercepted: count: {count}, min bond: {min_bond}");
+                let eligible = listed(
+                    &servers,
+                    &listing_policy,
+                    registration.min_bond_confirmations,
+                    tip,
+                )
+                .filter(|x| match &x.1.bond {
+                    Some(bond) => bond.amount >= min_bond,
+                    None => min_bond == Amount::ZERO,
+                });
+                let response = match seed {
+                    Some(seed) => sample(
+                        eligible,
+                        count as usize,
+                        tip,
+                        &mut StdRng::seed_from_u64(seed),
+                    ),
+                    None => sample(eligible, count as usize, tip, &mut rng),
+                };
                 let _ = resp_tx.send(response).await;
             }
             DbRequest::WatchUtxo(outpoint, resp_tx) => {
                 info!("Watch utxo intercepted");
 
+                // Registering the outpoint keeps its spends indexed in light mode.
+                if let Err(e) = diesel::insert_or_ignore_into(watched_outpoints::table)
+                    .values(&WatchedOutpoint::from(&outpoint))
+                    .execute(&mut conn)
+                {
+                    error!("Failed to register watched outpoint {outpoint}: {e}");
+                }
+
                 let mempool_tx = mempool_tx::table
                     .inner_join(mempool_inputs::table.on(mempool_tx::txid.eq(mempool_inputs::txid)))
                     .filter(mempool_inputs::input_txid.eq(outpoint.txid.to_string()))
@@ -64,6 +396,164 @@ pub async fn run(
 
                 let _ = resp_tx.send(mempool_tx).await;
             }
+            DbRequest::RevokeBonds(outpoints) => {
+                info!("Revoke bonds intercepted");
+                set_state(
+                    &mut conn,
+                    &mut servers,
+                    &events,
+                    MakerState::Revoked,
+                    "bond spent",
+                    |_, bond, _| outpoints.contains(&bond.outpoint),
+                );
+            }
+            DbRequest::ReinstateBonds(outpoints) => {
+                info!("Reinstate bonds intercepted");
+                for (address, info) in servers.iter_mut() {
+                    let reinstated = info.state == MakerState::Revoked
+                        && info
+                            .bond
+                            .as_ref()
+                            .is_some_and(|bond| outpoints.contains(&bond.outpoint));
+                    if !reinstated {
+                        continue;
+                    }
+                    transition(
+                        &mut conn,
+                        address,
+                        Some(info.state),
+                        info,
+                        MakerState::Pending,
+                        "bond spend left the mempool",
+                    );
+                    persist_server(&mut conn, address, info);
+                    let _ = events.send(RegistryEvent::Updated(address.clone(), info.clone()));
+                }
+            }
+            DbRequest::ExpireBonds {
+                height,
+                median_time,
+            } => {
+                tip = Some(Tip {
+                    height,
+                    median_time,
+                });
+                // Certificates are re-validated against every new tip.
+                // Announced makers have no certificate to check. Shallow
+                // bonds only keep their makers from being listed.
+                set_state(
+                    &mut conn,
+                    &mut servers,
+                    &events,
+                    MakerState::Expired,
+                    "bond or certificate expired",
+                    |address, bond, info| {
+                        let invalid_cert = info.certificate.as_ref().is_some_and(|cert| {
+                            verify_certificate(bond, cert, address, height).is_err()
+                        });
+                        bond.is_expired(height, median_time) || invalid_cert
+                    },
+                );
+            }
+            DbRequest::UpdateAddress {
+                old_address,
+                new_address,
+                bond,
+                certified,
+                nonce,
+            } => {
+                info!("Update address intercepted: {old_address} -> {new_address}");
+                if !is_backed_by(&servers, &old_address, &bond) {
+                    warn!("Ignoring address update for {old_address}");
+                    continue;
+                }
+                if servers.contains_key(&new_address) {
+                    warn!("Ignoring address update to already registered {new_address}");
+                    continue;
+                }
+                let mut info = servers[&old_address].clone();
+                // A certificate commits to the address, so a certified maker
+                // needs a new one for the address it moves to.
+                match certified {
+                    Some((bond, certificate)) => {
+                        info.bond = Some(bond);
+                        info.certificate = Some(certificate);
+                    }
+                    None if info.certificate.is_some() => {
+                        warn!("Ignoring address update for {old_address} without a certificate");
+                        continue;
+                    }
+                    None => {}
+                }
+                // The nonce is only used up by a request that would apply.
+                if !use_nonce(&mut conn, &mut used_nonces, &bond, nonce) {
+                    warn!("Ignoring replayed address update for {old_address}");
+                    continue;
+                }
+                info.onion_address = new_address.clone();
+                let result = conn.transaction(|conn| {
+                    probes::rename(conn, &old_address, &new_address)?;
+                    offers::rename(conn, &old_address, &new_address)?;
+                    versions::rename(conn, &old_address, &new_address)?;
+                    delete_server(conn, &old_address)?;
+                    write_server(conn, &new_address, &info)
+                });
+                if let Err(e) = result {
+                    error!("Failed to move server {old_address} to {new_address}: {e}");
+                    continue;
+                }
+                servers.remove(&old_address);
+                let _ = events.send(RegistryEvent::Removed(old_address));
+                let _ = events.send(RegistryEvent::Added(new_address.clone(), info.clone()));
+                servers.insert(new_address, info);
+            }
+            DbRequest::Deregister {
+                address,
+                bond,
+                nonce,
+            } => {
+                info!("Deregister intercepted: {address}");
+                if !is_backed_by(&servers, &address, &bond)
+                    || !use_nonce(&mut conn, &mut used_nonces, &bond, nonce)
+                {
+                    warn!("Ignoring deregistration of {address}");
+                    continue;
+                }
+                if let Err(e) = conn.transaction(|conn| delete_server(conn, &address)) {
+                    error!("Failed to delete server {address}: {e}");
+                    continue;
+                }
+                servers.remove(&address);
+                let _ = events.send(RegistryEvent::Removed(address));
+            }
+            DbRequest::ReportLiveness {
+                address,
+                bond,
+                nonce,
+            } => {
+                info!("Liveness report intercepted: {address}");
+                if !is_backed_by(&servers, &address, &bond)
+                    || !use_nonce(&mut conn, &mut used_nonces, &bond, nonce)
+                {
+                    warn!("Ignoring liveness report of {address}");
+                    continue;
+                }
+                let info = servers.get_mut(&address).expect("checked above");
+                mark_seen(info, Utc::now());
+                let next = lifecycle.next_state(info.state, 0, Duration::ZERO);
+                if next != info.state {
+                    transition(
+                        &mut conn,
+                        &address,
+                        Some(info.state),
+                        info,
+                        next,
+                        "reported liveness",
+                    );
+                    let _ = events.send(RegistryEvent::Updated(address.clone(), info.clone()));
+                }
+                persist_server(&mut conn, &address, info);
+            }
         }
     }
 
@@ -73,3 +563,727 @@ pub async fn run(
         })
         .await;
 }
+
+fn load_servers(conn: &mut SqliteConnection) -> Result<HashMap<String, ServerInfo>, TrackerError> {
+    let rows = servers::table
+        .select((
+            servers::onion_address,
+            servers::state,
+            servers::first_seen,
+            servers::last_seen,
+            servers::failures,
+        ))
+        .load::<(
+            Option<String>,
+            String,
+            Option<NaiveDateTime>,
+            Option<NaiveDateTime>,
+            i32,
+        )>(conn)?;
+    let mut bonds: HashMap<String, Bond> = fidelity_bonds::table
+        .load::<Bond>(conn)?
+        .into_iter()
+        .map(|bond| (bond.onion_address.clone(), bond))
+        .collect();
+    Ok(rows
+        .into_iter()
+        .filter_map(|(address, state, first_seen, last_seen, failures)| {
+            let address = address?;
+            let bond = bonds.remove(&address);
+            let info = ServerInfo {
+                onion_address: address.clone(),
+                cooldown: Instant::now(),
+                failures: failures as u32,
+                up_since: None,
+                first_seen: first_seen.map_or_else(Utc::now, |t| t.and_utc()),
+                last_seen: last_seen.map(|t| t.and_utc()),
+                bond: bond.as_ref().and_then(Bond::fidelity_bond),
+                certificate: bond.as_ref().and_then(Bond::certificate),
+                state: state.parse().unwrap_or_default(),
+            };
+            Some((address, info))
+        })
+        .collect())
+}
+
+/// Makers handed to takers: active, answering pings, bonded at least
+/// `min_confirmations` deep if bonded at all, and allowed by `policy`.
+fn listed<'a>(
+    servers: &'a HashMap<String, ServerInfo>,
+    policy: &'a ListingPolicy,
+    min_confirmations: u32,
+    tip: Option<Tip>,
+) -> impl Iterator<Item = (&'a String, &'a ServerInfo)> {
+    servers
+        .iter()
+        .filter(|x| x.1.state.is_listed())
+        .filter(move |x| match (&x.1.bond, tip) {
+            (None, _) => true,
+            (Some(bond), Some(tip)) => verify_depth(bond, tip.height, min_confirmations).is_ok(),
+            (Some(_), None) => min_confirmations == 0,
+        })
+        .filter(move |x| policy.allows(x.0, x.1, tip))
+}
+
+fn maker_record(
+    address: &str,
+    info: &ServerInfo,
+    tip: Option<Tip>,
+    stats: Option<&ProbeStats>,
+    version: Option<&MakerVersion>,
+) -> MakerRecord {
+    let stats = stats.copied().unwrap_or_default();
+    let score = match (&info.bond, tip) {
+        (Some(bond), Some(tip)) => bond_score(bond, tip),
+        _ => 0.0,
+    };
+    MakerRecord {
+        address: address.to_string(),
+        bond: info.bond.as_ref().map(BondRecord::from),
+        score,
+        first_seen: info.first_seen,
+        last_seen: info.last_seen,
+        uptime: stats.uptime,
+        latency_ms: stats.latency_ms,
+        protocol_version: version.and_then(MakerVersion::latest),
+        software_version: version.map(|version| version.software.clone()),
+    }
+}
+
+fn probe_stats(conn: &mut SqliteConnection) -> HashMap<String, ProbeStats> {
+    probes::rollups(conn, Utc::now()).unwrap_or_else(|e| {
+        error!("Failed to roll up probes: {e}");
+        HashMap::new()
+    })
+}
+
+fn maker_versions(conn: &mut SqliteConnection) -> HashMap<String, MakerVersion> {
+    versions::load(conn).unwrap_or_else(|e| {
+        error!("Failed to load versions: {e}");
+        HashMap::new()
+    })
+}
+
+fn persist_server(conn: &mut SqliteConnection, address: &str, info: &ServerInfo) {
+    if let Err(e) = conn.transaction(|conn| write_server(conn, address, info)) {
+        error!("Failed to persist server {address}: {e}");
+    }
+}
+
+fn write_server(
+    conn: &mut SqliteConnection,
+    address: &str,
+    info: &ServerInfo,
+) -> diesel::QueryResult<()> {
+    let row = Server {
+        onion_address: address.to_string(),
+        cooldown_seconds: 0.0,
+        state: info.state.as_str().to_string(),
+        first_seen: Some(info.first_seen.naive_utc()),
+        last_seen: info.last_seen.map(|t| t.naive_utc()),
+        failures: info.failures as i32,
+    };
+    diesel::replace_into(servers::table)
+        .values(&row)
+        .execute(conn)?;
+    match &info.bond {
+        Some(bond) => diesel::replace_into(fidelity_bonds::table)
+            .values(&Bond::new(address, bond, info.certificate.as_ref()))
+            .execute(conn)?,
+        None => {
+            diesel::delete(fidelity_bonds::table.filter(fidelity_bonds::onion_address.eq(address)))
+                .execute(conn)?
+        }
+    };
+    Ok(())
+}
+
+fn delete_server(conn: &mut SqliteConnection, address: &str) -> diesel::QueryResult<()> {
+    diesel::delete(servers::table.filter(servers::onion_address.eq(address))).execute(conn)?;
+    diesel::delete(fidelity_bonds::table.filter(fidelity_bonds::onion_address.eq(address)))
+        .execute(conn)?;
+    probes::delete(conn, address)?;
+    offers::delete(conn, address)?;
+    versions::delete(conn, address)?;
+    Ok(())
+}
+
+/// Finds active makers at other addresses sharing `info`'s bond outpoint or
+/// pubkey. Under `LatestWins` they are demoted to duplicates; under
+/// `FirstWins` the returned conflicts reject `address` instead.
+fn resolve_conflicts(
+    conn: &mut SqliteConnection,
+    servers: &mut HashMap<String, ServerInfo>,
+    events: &broadcast::Sender<RegistryEvent>,
+    policy: SybilPolicy,
+    address: &str,
+    info: &ServerInfo,
+) -> Vec<BondConflict> {
+    let Some(bond) = &info.bond else {
+        return Vec::new();
+    };
+    let now = Utc::now();
+    let mut found = Vec::new();
+    for (other_address, other) in servers.iter_mut() {
+        let shares_bond = other.bond.as_ref().is_some_and(|other_bond| {
+            other_bond.outpoint == bond.outpoint || other_bond.pubkey == bond.pubkey
+        });
+        if other_address == address || !other.state.is_probed() || !shares_bond {
+            continue;
+        }
+        let (kept, rejected) = match policy {
+            SybilPolicy::LatestWins => {
+                let reason = format!("bond reused by {address}");
+                let from = Some(other.state);
+                transition(
+                    conn,
+                    other_address,
+                    from,
+                    other,
+                    MakerState::Duplicate,
+                    &reason,
+                );
+                persist_server(conn, other_address, other);
+                let _ = events.send(RegistryEvent::Updated(other_address.clone(), other.clone()));
+                (address.to_string(), other_address.clone())
+            }
+            SybilPolicy::FirstWins => (other_address.clone(), address.to_string()),
+        };
+        found.push(BondConflict {
+            outpoint: bond.outpoint,
+            kept,
+            rejected,
+            at: now,
+        });
+    }
+    found
+}
+
+/// Applies `policy` to the stored makers in the order they were first seen,
+/// as if they had registered again, and persists the outcome. Stores written
+/// by earlier versions, or under another policy, may hold makers that lose a
+/// conflict: under `FirstWins` they become duplicates instead of being
+/// refused.
+fn resolve_loaded_conflicts(
+    conn: &mut SqliteConnection,
+    servers: &mut HashMap<String, ServerInfo>,
+    events: &broadcast::Sender<RegistryEvent>,
+    policy: SybilPolicy,
+) -> Vec<BondConflict> {
+    let mut order: Vec<(DateTime<Utc>, String)> = servers
+        .iter()
+        .filter(|(_, info)| info.state.is_probed() && info.bond.is_some())
+        .map(|(address, info)| (info.first_seen, address.clone()))
+        .collect();
+    order.sort();
+    let mut checked = HashMap::new();
+    let mut found = Vec::new();
+    for (_, address) in order {
+        let mut info = servers.remove(&address).expect("listed above");
+        let conflicts = resolve_conflicts(conn, &mut checked, events, policy, &address, &info);
+        if let Some(conflict) = conflicts.iter().find(|c| c.rejected == address) {
+            let reason = format!("bond already used by {}", conflict.kept);
+            let from = Some(info.state);
+            transition(
+                conn,
+                &address,
+                from,
+                &mut info,
+                MakerState::Duplicate,
+                &reason,
+            );
+            persist_server(conn, &address, &info);
+        }
+        found.extend(conflicts);
+        checked.insert(address, info);
+    }
+    servers.extend(checked);
+    found
+}
+
+/// Records that the maker answered a probe or reported itself alive at `now`.
+fn mark_seen(info: &mut ServerInfo, now: DateTime<Utc>) {
+    info.cooldown = Instant::now();
+    info.failures = 0;
+    info.up_since.get_or_insert_with(Instant::now);
+    info.last_seen = Some(now);
+}
+
+/// Keeps the certificate `known` was registered with when `info` registers
+/// the same bond again without one, as announcements do.
+fn keep_certificate(info: &mut ServerInfo, known: &ServerInfo) {
+    let (Some(bond), Some(known_bond)) = (&mut info.bond, &known.bond) else {
+        return;
+    };
+    if info.certificate.is_none() && bond.outpoint == known_bond.outpoint {
+        info.certificate = known.certificate.clone();
+        bond.cert_expiry = bond.cert_expiry.or(known_bond.cert_expiry);
+    }
+}
+
+/// Whether `info` would replace the bond `known` stands on with a smaller
+/// one under the same key. Announcements do not sign the bond amount, so
+/// anyone can broadcast a maker's announcement again next to a dust bond;
+/// its own bond stays in place until it is spent or expires.
+fn downgrades_bond(info: &ServerInfo, known: &ServerInfo) -> bool {
+    let (Some(bond), Some(known_bond)) = (&info.bond, &known.bond) else {
+        return false;
+    };
+    known.state.is_probed()
+        && bond.outpoint != known_bond.outpoint
+        && bond.pubkey == known_bond.pubkey
+        && bond.amount < known_bond.amount
+}
+
+/// Whether `address` is registered with the bond at `outpoint`.
+fn is_backed_by(servers: &HashMap<String, ServerInfo>, address: &str, outpoint: &OutPoint) -> bool {
+    servers
+        .get(address)
+        .and_then(|info| info.bond.as_ref())
+        .is_some_and(|bond| bond.outpoint == *outpoint)
+}
+
+/// Records `nonce` for `bond`, returning `false` if the bond already used it
+/// or it could not be stored. Nonces are persisted so that restarting the
+/// tracker does not reopen the window for replaying a request. Callers check
+/// that the bond backs the request first, so no one else can use up a
+/// maker's nonces.
+fn use_nonce(
+    conn: &mut SqliteConnection,
+    used_nonces: &mut HashMap<(OutPoint, u64), DateTime<Utc>>,
+    bond: &OutPoint,
+    nonce: u64,
+) -> bool {
+    let now = Utc::now();
+    let expired = now - NONCE_RETENTION;
+    used_nonces.retain(|_, used_at| *used_at >= expired);
+    if used_nonces.contains_key(&(*bond, nonce)) {
+        return false;
+    }
+    if let Err(e) = nonces::record(conn, bond, nonce, now, expired) {
+        error!("Failed to record nonce: {e}");
+        return false;
+    }
+    used_nonces.insert((*bond, nonce), now);
+    true
+}
+
+/// Moves every bonded maker in good standing for which `affected` holds to
+/// `state`.
+fn set_state(
+    conn: &mut SqliteConnection,
+    servers: &mut HashMap<String, ServerInfo>,
+    events: &broadcast::Sender<RegistryEvent>,
+    state: MakerState,
+    reason: &str,
+    affected: impl Fn(&str, &FidelityBond, &ServerInfo) -> bool,
+) {
+    for (address, info) in servers.iter_mut() {
+        let Some(bond) = &info.bond else {
+            continue;
+        };
+        if !info.state.is_probed() || !affected(address, bond, info) {
+            continue;
+        }
+        transition(conn, address, Some(info.state), info, state, reason);
+        persist_server(conn, address, info);
+        let _ = events.send(RegistryEvent::Updated(address.clone(), info.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::schema::maker_transitions,
+        test_utils::{self, bond, certify, file_pool, memory_db, server},
+        types::{Certificate, Uptime},
+    };
+    use bitcoincore_rpc::bitcoin::{PublicKey, secp256k1::Secp256k1};
+    use chrono::DateTime;
+    use tokio::sync::mpsc;
+
+    /// Maker at `address` with bond `tag`, keyed by `test_utils::key(key)`.
+    fn maker(address: &str, tag: u8, key: u8) -> ServerInfo {
+        let key = test_utils::key(key);
+        let bond = FidelityBond {
+            pubkey: PublicKey::new(key.public_key(&Secp256k1::new())),
+            ..bond(tag)
+        };
+        server(address, Some(bond))
+    }
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let mut conn = memory_db();
+
+        let mut servers = HashMap::from([
+            ("a:1".to_string(), maker("a:1", 1, 1)),
+            ("b:1".to_string(), maker("b:1", 2, 2)),
+        ]);
+        let (events_tx, mut events_rx) = broadcast::channel(16);
+
+        // A distinct bond and key does not conflict.
+        let unrelated = maker("c:1", 3, 3);
+        let found = resolve_conflicts(
+            &mut conn,
+            &mut servers,
+            &events_tx,
+            SybilPolicy::LatestWins,
+            "c:1",
+            &unrelated,
+        );
+        assert!(found.is_empty());
+
+        // Same outpoint under first-wins: the newcomer is rejected.
+        let same_outpoint = maker("d:1", 1, 4);
+        let found = resolve_conflicts(
+            &mut conn,
+            &mut servers,
+            &events_tx,
+            SybilPolicy::FirstWins,
+            "d:1",
+            &same_outpoint,
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            (found[0].kept.as_str(), found[0].rejected.as_str()),
+            ("a:1", "d:1")
+        );
+        assert_eq!(servers["a:1"].state, MakerState::Active);
+
+        // Same pubkey under latest-wins: the existing maker is demoted.
+        let same_key = maker("e:1", 5, 2);
+        let found = resolve_conflicts(
+            &mut conn,
+            &mut servers,
+            &events_tx,
+            SybilPolicy::LatestWins,
+            "e:1",
+            &same_key,
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            (found[0].kept.as_str(), found[0].rejected.as_str()),
+            ("e:1", "b:1")
+        );
+        assert_eq!(servers["b:1"].state, MakerState::Duplicate);
+
+        // Only the demotion changed a state, and it was audited.
+        let audited = maker_transitions::table
+            .select((
+                maker_transitions::onion_address,
+                maker_transitions::to_state,
+            ))
+            .load::<(String, String)>(&mut conn)
+            .unwrap();
+        assert_eq!(audited, vec![("b:1".to_string(), "duplicate".to_string())]);
+        match events_rx.try_recv().unwrap() {
+            RegistryEvent::Updated(address, info) => {
+                assert_eq!(
+                    (address.as_str(), info.state),
+                    ("b:1", MakerState::Duplicate)
+                );
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+        assert!(events_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_maker_record() {
+        let mut conn = memory_db();
+
+        let mut info = maker("a:1", 1, 1);
+        info.first_seen = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        info.last_seen = DateTime::from_timestamp(1_700_003_600, 0);
+        persist_server(&mut conn, "a:1", &info);
+
+        // Activity survives a restart.
+        let loaded = load_servers(&mut conn).unwrap().remove("a:1").unwrap();
+        assert_eq!(loaded.first_seen, info.first_seen);
+        assert_eq!(loaded.last_seen, info.last_seen);
+
+        let tip = Tip {
+            height: 850_100,
+            median_time: 0,
+        };
+        let stats = ProbeStats {
+            uptime: Uptime {
+                hour: Some(1.0),
+                day: Some(0.75),
+                week: Some(0.5),
+            },
+            latency_ms: Some(420),
+        };
+        let version = test_utils::version("coinswap/0.2.0", &[2, 1]);
+        let record = maker_record("a:1", &info, Some(tip), Some(&stats), Some(&version));
+        assert_eq!(record.bond, info.bond.as_ref().map(BondRecord::from));
+        assert_eq!(record.uptime, stats.uptime);
+        assert_eq!(record.latency_ms, Some(420));
+        assert_eq!(record.protocol_version, Some(2));
+        assert_eq!(record.software_version.as_deref(), Some("coinswap/0.2.0"));
+        assert!(record.score > 0.0);
+        assert_eq!(record.score, bond_score(info.bond.as_ref().unwrap(), tip));
+
+        let record = maker_record("a:1", &loaded, None, None, None);
+        assert_eq!(record.uptime, Uptime::default());
+        assert_eq!(record.score, 0.0);
+        assert_eq!(record.protocol_version, None);
+    }
+
+    #[test]
+    fn test_resolve_loaded_conflicts() {
+        // An active maker and a later pending registration of its bond, as
+        // stored without going through conflict resolution.
+        let mut first = maker("a:1", 1, 1);
+        first.first_seen = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut second = maker("b:1", 1, 2);
+        second.first_seen = DateTime::from_timestamp(1_700_000_100, 0).unwrap();
+        second.state = MakerState::Pending;
+
+        for (policy, kept, rejected) in [
+            (SybilPolicy::FirstWins, "a:1", "b:1"),
+            (SybilPolicy::LatestWins, "b:1", "a:1"),
+        ] {
+            let mut conn = memory_db();
+            write_server(&mut conn, "a:1", &first).unwrap();
+            write_server(&mut conn, "b:1", &second).unwrap();
+            let (events_tx, _events_rx) = broadcast::channel(16);
+
+            let mut servers = load_servers(&mut conn).unwrap();
+            let found = resolve_loaded_conflicts(&mut conn, &mut servers, &events_tx, policy);
+            assert_eq!(found.len(), 1);
+            assert_eq!(
+                (found[0].kept.as_str(), found[0].rejected.as_str()),
+                (kept, rejected)
+            );
+            assert_eq!(servers.len(), 2);
+            assert_eq!(servers[rejected].state, MakerState::Duplicate);
+            assert!(servers[kept].state.is_probed());
+
+            // The outcome is persisted, so the next start finds nothing to do.
+            let mut servers = load_servers(&mut conn).unwrap();
+            assert_eq!(servers[rejected].state, MakerState::Duplicate);
+            let found = resolve_loaded_conflicts(&mut conn, &mut servers, &events_tx, policy);
+            assert!(found.is_empty());
+        }
+    }
+
+    /// Bond 1 certified for `address`.
+    fn certified(address: &str) -> (FidelityBond, Certificate) {
+        certify(bond(1), &test_utils::key(1), address)
+    }
+
+    /// Runs a DB manager over a database in `dir`, with latest-wins sybil
+    /// resolution and 6 confirmations required of certified bonds.
+    fn spawn_manager(dir: &tempfile::TempDir) -> mpsc::Sender<DbRequest> {
+        let (db_tx, db_rx) = mpsc::channel(16);
+        let (status_tx, _status_rx) = mpsc::channel(16);
+        let (events_tx, _events_rx) = broadcast::channel(16);
+        let registration = RegistrationPolicy {
+            sybil: SybilPolicy::LatestWins,
+            min_bond_confirmations: 6,
+        };
+        tokio::spawn(run(
+            file_pool(dir),
+            db_rx,
+            status::Sender::DBManager(status_tx),
+            registration,
+            ListingPolicy::default(),
+            Lifecycle::default(),
+            events_tx,
+        ));
+        db_tx
+    }
+
+    async fn query(db_tx: &mpsc::Sender<DbRequest>, address: &str) -> Option<ServerInfo> {
+        let (resp_tx, mut resp_rx) = mpsc::channel(1);
+        db_tx
+            .send(DbRequest::Query(address.to_string(), resp_tx))
+            .await
+            .unwrap();
+        resp_rx.recv().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replayed_announcement_keeps_bond() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_tx = spawn_manager(&dir);
+        let address = "maker.onion:6102";
+        let add = |info| DbRequest::Add(address.to_string(), info);
+        db_tx.send(add(maker(address, 1, 1))).await.unwrap();
+
+        // The same announcement replayed next to a dust bond under the same
+        // key does not replace the maker's bond.
+        let mut dust = maker(address, 2, 1);
+        dust.bond.as_mut().unwrap().amount = Amount::from_sat(546);
+        db_tx.send(add(dust.clone())).await.unwrap();
+        let kept = query(&db_tx, address).await.unwrap().bond.unwrap();
+        assert_eq!(kept.outpoint, bond(1).outpoint);
+
+        // A larger bond does, and once the maker's bond is spent a smaller
+        // one is accepted again.
+        let mut larger = maker(address, 3, 1);
+        larger.bond.as_mut().unwrap().amount = Amount::from_sat(10_000_000);
+        db_tx.send(add(larger)).await.unwrap();
+        let kept = query(&db_tx, address).await.unwrap().bond.unwrap();
+        assert_eq!(kept.outpoint, bond(3).outpoint);
+        db_tx
+            .send(DbRequest::RevokeBonds(vec![bond(3).outpoint]))
+            .await
+            .unwrap();
+        db_tx.send(add(dust)).await.unwrap();
+        let kept = query(&db_tx, address).await.unwrap().bond.unwrap();
+        assert_eq!(kept.outpoint, bond(2).outpoint);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_mempool_spend_reinstates_maker() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_tx = spawn_manager(&dir);
+        let address = "maker.onion:6102";
+        db_tx
+            .send(DbRequest::Add(address.to_string(), maker(address, 1, 1)))
+            .await
+            .unwrap();
+
+        let outpoint = bond(1).outpoint;
+        db_tx
+            .send(DbRequest::RevokeBonds(vec![outpoint]))
+            .await
+            .unwrap();
+        assert_eq!(
+            query(&db_tx, address).await.unwrap().state,
+            MakerState::Revoked
+        );
+        db_tx
+            .send(DbRequest::ReinstateBonds(vec![outpoint]))
+            .await
+            .unwrap();
+        assert_eq!(
+            query(&db_tx, address).await.unwrap().state,
+            MakerState::Pending
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_certified_maker() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_tx = spawn_manager(&dir);
+        let query = |address: &'static str| query(&db_tx, address);
+
+        let (old, new) = ("old.onion:6102", "new.onion:6102");
+        let (bond, certificate) = certified(old);
+        let mut info = maker(old, 1, 1);
+        info.bond = Some(bond.clone());
+        info.certificate = Some(certificate);
+        db_tx
+            .send(DbRequest::Add(old.to_string(), info))
+            .await
+            .unwrap();
+
+        // A certified maker cannot move without a certificate for the new
+        // address.
+        let update = |certified, nonce| DbRequest::UpdateAddress {
+            old_address: old.to_string(),
+            new_address: new.to_string(),
+            bond: bond.outpoint,
+            certified,
+            nonce,
+        };
+        db_tx.send(update(None, 1)).await.unwrap();
+        assert!(query(old).await.is_some());
+        assert!(query(new).await.is_none());
+        // The ignored request did not use up its nonce.
+        db_tx.send(update(Some(certified(new)), 1)).await.unwrap();
+        assert!(query(old).await.is_none());
+
+        // Announcing the bond again keeps the certificate.
+        let mut announced = maker(new, 1, 1);
+        announced.certificate = None;
+        db_tx
+            .send(DbRequest::Add(new.to_string(), announced))
+            .await
+            .unwrap();
+        let moved = query(new).await.unwrap();
+        assert_eq!(moved.certificate, Some(certified(new).1));
+
+        // A certified maker whose bond confirmed two blocks below the tip
+        // is too shallow to be listed, as is an announced one without a
+        // certificate, while the moved maker's deep bond keeps its
+        // certificate valid at the new address.
+        let announced = "announced.onion:6102";
+        let bond = FidelityBond {
+            conf_height: Some(850_008),
+            ..maker(announced, 3, 3).bond.unwrap()
+        };
+        db_tx
+            .send(DbRequest::Add(
+                announced.to_string(),
+                server(announced, Some(bond)),
+            ))
+            .await
+            .unwrap();
+        let shallow = "shallow.onion:6102";
+        let bond = FidelityBond {
+            conf_height: Some(850_008),
+            ..maker(shallow, 2, 2).bond.unwrap()
+        };
+        let (bond, certificate) = certify(bond, &test_utils::key(2), shallow);
+        let info = ServerInfo {
+            certificate: Some(certificate),
+            ..server(shallow, Some(bond))
+        };
+        db_tx
+            .send(DbRequest::Add(shallow.to_string(), info))
+            .await
+            .unwrap();
+        let active = || async {
+            let (resp_tx, mut resp_rx) = mpsc::channel(1);
+            db_tx
+                .send(DbRequest::QueryActive(None, resp_tx))
+                .await
+                .unwrap();
+            let mut active = resp_rx.recv().await.unwrap();
+            active.sort();
+            active
+        };
+        let expire = |height| DbRequest::ExpireBonds {
+            height,
+            median_time: 0,
+        };
+        db_tx.send(expire(850_010)).await.unwrap();
+        assert_eq!(query(new).await.unwrap().state, MakerState::Active);
+        assert_eq!(query(shallow).await.unwrap().state, MakerState::Active);
+        assert_eq!(query(announced).await.unwrap().state, MakerState::Active);
+        assert_eq!(active().await, vec![new.to_string()]);
+
+        // Both are listed once their bonds are deep enough.
+        db_tx.send(expire(850_013)).await.unwrap();
+        assert_eq!(active().await, vec![announced, new, shallow]);
+    }
+
+    #[test]
+    fn test_nonces_survive_restart() {
+        let mut conn = memory_db();
+
+        let (own, other) = (bond(1).outpoint, bond(2).outpoint);
+        let mut used_nonces = HashMap::new();
+        assert!(use_nonce(&mut conn, &mut used_nonces, &own, 7));
+        assert!(!use_nonce(&mut conn, &mut used_nonces, &own, 7));
+        // Nonces of one bond do not use up those of another.
+        assert!(use_nonce(&mut conn, &mut used_nonces, &other, 8));
+
+        // A restarted manager still refuses the nonce.
+        let mut used_nonces = nonces::load(&mut conn, Utc::now() - NONCE_RETENTION).unwrap();
+        assert!(!use_nonce(&mut conn, &mut used_nonces, &own, 7));
+        assert!(use_nonce(&mut conn, &mut used_nonces, &own, 8));
+
+        // Nonces older than the retention are forgotten.
+        let later = Utc::now() + NONCE_RETENTION + chrono::Duration::seconds(1);
+        nonces::record(&mut conn, &own, 9, later, later - NONCE_RETENTION).unwrap();
+        let loaded = nonces::load(&mut conn, later - NONCE_RETENTION).unwrap();
+        assert_eq!(loaded.keys().copied().collect::<Vec<_>>(), vec![(own, 9)]);
+    }
+}
@@ -0,0 +1,134 @@
+//! Liveness transitions of makers and their audit trail.
+//!
+//! A registered maker starts out `Pending` and becomes `Active` once it
+//! answers a probe. Consecutive failed probes degrade it and then make it
+//! `Stale`, and a maker that has not answered for `evict_after` is `Evicted`
+//! and no longer probed. Every change of state, including the bond and
+//! operator driven ones, is recorded in `maker_transitions`.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::{SqliteConnection, prelude::*};
+use tracing::{error, info};
+
+use crate::{
+    db::{model::MakerTransition, schema::maker_transitions},
+    types::{MakerState, ServerInfo},
+};
+
+/// Thresholds driving the liveness states.
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    /// Consecutive failed probes after which an active maker is degraded.
+    pub degraded_after: u32,
+    /// Consecutive failed probes after which a maker is stale and no longer
+    /// listed.
+    pub stale_after: u32,
+    /// Time without an answer, counted from registration for makers that
+    /// never answered, after which a maker is evicted.
+    pub evict_after: Duration,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            degraded_after: 2,
+            stale_after: 5,
+            evict_after: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl Lifecycle {
+    /// State after a probe, for a maker in `state` that has now failed
+    /// `failures` probes in a row and last answered `silent_for` ago.
+    pub fn next_state(&self, state: MakerState, failures: u32, silent_for: Duration) -> MakerState {
+        if !state.is_probed() {
+            return state;
+        }
+        if failures == 0 {
+            return MakerState::Active;
+        }
+        if silent_for >= self.evict_after {
+            return MakerState::Evicted;
+        }
+        if failures >= self.stale_after {
+            return MakerState::Stale;
+        }
+        match state {
+            MakerState::Active | MakerState::Degraded if failures >= self.degraded_after => {
+                MakerState::Degraded
+            }
+            state => state,
+        }
+    }
+}
+
+/// Moves `info` to `to` and records the change. `from` is the state being
+/// left, `None` for a maker that was not registered before.
+pub(crate) fn transition(
+    conn: &mut SqliteConnection,
+    address: &str,
+    from: Option<MakerState>,
+    info: &mut ServerInfo,
+    to: MakerState,
+    reason: &str,
+) {
+    info!(
+        "Maker {address}: {} -> {} ({reason})",
+        from.map_or("none", |from| from.as_str()),
+        to.as_str()
+    );
+    info.state = to;
+    let row = MakerTransition {
+        onion_address: address.to_string(),
+        from_state: from.map(|from| from.as_str().to_string()),
+        to_state: to.as_str().to_string(),
+        reason: reason.to_string(),
+        at: Utc::now().naive_utc(),
+    };
+    if let Err(e) = diesel::insert_into(maker_transitions::table)
+        .values(&row)
+        .execute(conn)
+    {
+        error!("Failed to record transition of {address}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MakerState::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_next_state() {
+        let lifecycle = Lifecycle::default();
+        let month = lifecycle.evict_after;
+
+        // Answering probes makes any live maker active.
+        for state in [Pending, Active, Degraded, Stale] {
+            assert_eq!(lifecycle.next_state(state, 0, Duration::ZERO), Active);
+        }
+
+        // Failures degrade active makers, then make every live maker stale.
+        assert_eq!(lifecycle.next_state(Active, 1, HOUR), Active);
+        assert_eq!(lifecycle.next_state(Active, 2, HOUR), Degraded);
+        assert_eq!(lifecycle.next_state(Degraded, 4, HOUR), Degraded);
+        assert_eq!(lifecycle.next_state(Pending, 4, HOUR), Pending);
+        assert_eq!(lifecycle.next_state(Pending, 5, HOUR), Stale);
+        assert_eq!(lifecycle.next_state(Degraded, 5, HOUR), Stale);
+
+        // Long silence evicts, however few probes failed.
+        assert_eq!(lifecycle.next_state(Stale, 9, month), Evicted);
+        assert_eq!(lifecycle.next_state(Active, 1, month), Evicted);
+
+        // Bond and operator states are left alone.
+        for state in [Evicted, Banned, Revoked, Expired, Duplicate] {
+            assert_eq!(lifecycle.next_state(state, 0, Duration::ZERO), state);
+            assert_eq!(lifecycle.next_state(state, 9, month), state);
+        }
+    }
+}
@@ -1,4 +1,40 @@
+/// Defines `rename` and `delete` for a table of per-maker rows keyed by
+/// `onion_address`, so the rows follow a maker that moves and go away with
+/// it.
+macro_rules! maker_rows {
+    ($table:ident) => {
+        /// Moves the rows of a maker that changed its address.
+        pub(crate) fn rename(
+            conn: &mut diesel::SqliteConnection,
+            old: &str,
+            new: &str,
+        ) -> diesel::QueryResult<usize> {
+            use diesel::prelude::*;
+            diesel::update($table::table.filter($table::onion_address.eq(old)))
+                .set($table::onion_address.eq(new))
+                .execute(conn)
+        }
+
+        /// Removes the rows of a maker.
+        pub(crate) fn delete(
+            conn: &mut diesel::SqliteConnection,
+            address: &str,
+        ) -> diesel::QueryResult<usize> {
+            use diesel::prelude::*;
+            diesel::delete($table::table.filter($table::onion_address.eq(address))).execute(conn)
+        }
+    };
+}
+
 mod db_manager;
-pub use db_manager::run;
+pub mod lifecycle;
+mod nonces;
+pub use db_manager::{RegistrationPolicy, SybilPolicy, run};
 pub mod model;
+pub mod offers;
+pub mod policy;
+pub mod probes;
+pub mod pruner;
+mod sampling;
 pub mod schema;
+pub mod versions;
@@ -1,25 +1,140 @@
+use bitcoincore_rpc::bitcoin::{
+    Amount, OutPoint, PublicKey, ScriptBuf, TxOut, Txid,
+    absolute::LockTime,
+    hashes::{Hash, sha256d},
+    secp256k1::ecdsa::Signature,
+};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::types::{Certificate, FidelityBond, MakerVersion, Offer};
+
 #[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
 #[diesel(table_name = crate::db::schema::servers)]
 pub struct Server {
     pub onion_address: String,
     pub cooldown_seconds: f32,
-    pub stale: bool,
+    pub state: String,
+    pub first_seen: Option<chrono::NaiveDateTime>,
+    pub last_seen: Option<chrono::NaiveDateTime>,
+    pub failures: i32,
 }
 
 #[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
 #[diesel(table_name = crate::db::schema::utxos)]
 pub struct Utxo {
-    pub txid: String,
+    pub txid: Vec<u8>,
     pub vout: i32,
-    pub value: i32,
-    pub script_pubkey: String,
+    pub value: i64,
+    pub script_pubkey: Vec<u8>,
     pub confirmed: bool,
     pub spent: bool,
-    pub spent_by_txid: Option<String>,
+    pub spent_by_txid: Option<Vec<u8>>,
     pub block_height: Option<i32>,
+    pub spent_height: Option<i32>,
+}
+
+impl Utxo {
+    /// Builds an unspent row for `tx_out`. `height` is `None` for mempool outputs.
+    pub fn new(outpoint: &OutPoint, tx_out: &TxOut, height: Option<u64>) -> Self {
+        Self {
+            txid: txid_to_bytes(&outpoint.txid),
+            vout: outpoint.vout as i32,
+            value: tx_out.value.to_sat() as i64,
+            script_pubkey: tx_out.script_pubkey.to_bytes(),
+            confirmed: height.is_some(),
+            spent: false,
+            spent_by_txid: None,
+            block_height: height.map(|h| h as i32),
+            spent_height: None,
+        }
+    }
+
+    pub fn outpoint(&self) -> Option<OutPoint> {
+        Some(OutPoint::new(
+            txid_from_bytes(&self.txid)?,
+            self.vout as u32,
+        ))
+    }
+
+    pub fn tx_out(&self) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(self.value as u64),
+            script_pubkey: ScriptBuf::from_bytes(self.script_pubkey.clone()),
+        }
+    }
+
+    pub fn spent_by(&self) -> Option<Txid> {
+        txid_from_bytes(self.spent_by_txid.as_deref()?)
+    }
+}
+
+#[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
+#[diesel(table_name = crate::db::schema::fidelity_bonds)]
+pub struct Bond {
+    pub onion_address: String,
+    pub txid: Vec<u8>,
+    pub vout: i32,
+    pub amount: i64,
+    pub lock_time: i64,
+    pub pubkey: Vec<u8>,
+    pub conf_height: Option<i32>,
+    pub cert_expiry: Option<i32>,
+    pub cert_hash: Option<Vec<u8>>,
+    pub cert_sig: Option<Vec<u8>>,
+}
+
+impl Bond {
+    pub fn new(
+        onion_address: &str,
+        bond: &FidelityBond,
+        certificate: Option<&Certificate>,
+    ) -> Self {
+        Self {
+            onion_address: onion_address.to_string(),
+            txid: txid_to_bytes(&bond.outpoint.txid),
+            vout: bond.outpoint.vout as i32,
+            amount: bond.amount.to_sat() as i64,
+            lock_time: bond.lock_time.to_consensus_u32() as i64,
+            pubkey: bond.pubkey.to_bytes(),
+            conf_height: bond.conf_height.map(|h| h as i32),
+            cert_expiry: bond.cert_expiry.map(|e| e as i32),
+            cert_hash: certificate.map(|cert| cert.hash.to_byte_array().to_vec()),
+            cert_sig: certificate.map(|cert| cert.sig.serialize_compact().to_vec()),
+        }
+    }
+
+    pub fn fidelity_bond(&self) -> Option<FidelityBond> {
+        Some(FidelityBond {
+            outpoint: OutPoint::new(txid_from_bytes(&self.txid)?, self.vout as u32),
+            amount: Amount::from_sat(self.amount as u64),
+            lock_time: LockTime::from_consensus(self.lock_time as u32),
+            pubkey: PublicKey::from_slice(&self.pubkey).ok()?,
+            conf_height: self.conf_height.map(|h| h as u32),
+            cert_expiry: self.cert_expiry.map(|e| e as u32),
+        })
+    }
+
+    pub fn certificate(&self) -> Option<Certificate> {
+        Some(Certificate {
+            hash: sha256d::Hash::from_slice(self.cert_hash.as_deref()?).ok()?,
+            sig: Signature::from_compact(self.cert_sig.as_deref()?).ok()?,
+        })
+    }
+}
+
+/// Txids are stored in display byte order so that `hex(txid)` in SQLite
+/// matches what block explorers and bitcoind print.
+pub fn txid_to_bytes(txid: &Txid) -> Vec<u8> {
+    let mut bytes = txid.to_byte_array();
+    bytes.reverse();
+    bytes.to_vec()
+}
+
+pub fn txid_from_bytes(bytes: &[u8]) -> Option<Txid> {
+    let mut bytes: [u8; 32] = bytes.try_into().ok()?;
+    bytes.reverse();
+    Some(Txid::from_byte_array(bytes))
 }
 
 #[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
@@ -36,3 +151,272 @@ pub struct MempoolInput {
     pub input_txid: String,
     pub input_vout: i32,
 }
+
+/// One liveness probe of a maker.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::db::schema::maker_probes)]
+pub struct MakerProbe {
+    pub onion_address: String,
+    pub probed_at: chrono::NaiveDateTime,
+    pub success: bool,
+    pub latency_ms: Option<i64>,
+    pub error_kind: Option<String>,
+}
+
+/// A change of a maker's state, kept for auditing.
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::db::schema::maker_transitions)]
+pub struct MakerTransition {
+    pub onion_address: String,
+    pub from_state: Option<String>,
+    pub to_state: String,
+    pub reason: String,
+    pub at: chrono::NaiveDateTime,
+}
+
+/// The last offer fetched from a maker.
+#[derive(Queryable, Insertable, Debug)]
+#[diesel(table_name = crate::db::schema::maker_offers)]
+pub struct CachedOffer {
+    pub onion_address: String,
+    pub base_fee: i64,
+    pub amount_relative_fee_pct: f64,
+    pub time_relative_fee_pct: f64,
+    pub required_confirms: i32,
+    pub minimum_locktime: i32,
+    pub min_size: i64,
+    pub max_size: i64,
+    pub fetched_at: chrono::NaiveDateTime,
+}
+
+impl CachedOffer {
+    pub fn new(onion_address: &str, offer: &Offer, fetched_at: chrono::NaiveDateTime) -> Self {
+        Self {
+            onion_address: onion_address.to_string(),
+            base_fee: offer.base_fee as i64,
+            amount_relative_fee_pct: offer.amount_relative_fee_pct,
+            time_relative_fee_pct: offer.time_relative_fee_pct,
+            required_confirms: offer.required_confirms as i32,
+            minimum_locktime: offer.minimum_locktime as i32,
+            min_size: offer.min_size as i64,
+            max_size: offer.max_size as i64,
+            fetched_at,
+        }
+    }
+
+    pub fn offer(&self) -> Offer {
+        Offer {
+            base_fee: self.base_fee as u64,
+            amount_relative_fee_pct: self.amount_relative_fee_pct,
+            time_relative_fee_pct: self.time_relative_fee_pct,
+            required_confirms: self.required_confirms as u32,
+            minimum_locktime: self.minimum_locktime as u16,
+            min_size: self.min_size as u64,
+            max_size: self.max_size as u64,
+        }
+    }
+}
+
+/// The versions a maker last reported in a pong.
+#[derive(Queryable, Insertable, Debug)]
+#[diesel(table_name = crate::db::schema::maker_versions)]
+pub struct ReportedVersion {
+    pub onion_address: String,
+    pub software_version: String,
+    /// Comma separated.
+    pub protocol_versions: String,
+    pub reported_at: chrono::NaiveDateTime,
+}
+
+impl ReportedVersion {
+    pub fn new(
+        onion_address: &str,
+        version: &MakerVersion,
+        reported_at: chrono::NaiveDateTime,
+    ) -> Self {
+        let protocols: Vec<String> = version.protocols.iter().map(u32::to_string).collect();
+        Self {
+            onion_address: onion_address.to_string(),
+            software_version: version.software.clone(),
+            protocol_versions: protocols.join(","),
+            reported_at,
+        }
+    }
+
+    pub fn version(&self) -> MakerVersion {
+        MakerVersion {
+            software: self.software_version.clone(),
+            protocols: self
+                .protocol_versions
+                .split(',')
+                .filter_map(|protocol| protocol.parse().ok())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
+#[diesel(table_name = crate::db::schema::indexer_state)]
+pub struct IndexerState {
+    pub id: i32,
+    pub next_height: i64,
+}
+
+#[derive(Queryable, Insertable, Debug, Serialize, Deserialize)]
+#[diesel(table_name = crate::db::schema::watched_outpoints)]
+pub struct WatchedOutpoint {
+    pub txid: Vec<u8>,
+    pub vout: i32,
+}
+
+impl From<&OutPoint> for WatchedOutpoint {
+    fn from(outpoint: &OutPoint) -> Self {
+        Self {
+            txid: txid_to_bytes(&outpoint.txid),
+            vout: outpoint.vout as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::schema::utxos;
+    use crate::test_utils::memory_db;
+    use diesel::connection::SimpleConnection;
+    use diesel_migrations::MigrationHarness;
+
+    const TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    #[test]
+    fn test_utxo_roundtrips_amounts_above_i32() {
+        let mut conn = memory_db();
+
+        let outpoint = OutPoint::new(TXID.parse().unwrap(), 1);
+        let tx_out = TxOut {
+            value: Amount::from_btc(50.0).unwrap(),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x00, 0x14, 0xab]),
+        };
+        diesel::insert_into(utxos::table)
+            .values(&Utxo::new(&outpoint, &tx_out, Some(1)))
+            .execute(&mut conn)
+            .unwrap();
+
+        let stored = utxos::table.first::<Utxo>(&mut conn).unwrap();
+        assert_eq!(stored.outpoint(), Some(outpoint));
+        assert_eq!(stored.tx_out(), tx_out);
+        assert_eq!(hex::encode(&stored.txid), TXID);
+    }
+
+    #[test]
+    fn test_migration_converts_legacy_rows() {
+        use crate::db::schema::{indexer_state, mempool_tx, utxo_rechecks};
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        // Servers, indexer state and watched outpoints predate the binary columns.
+        for _ in 0..3 {
+            conn.run_next_migration(crate::MIGRATIONS).unwrap();
+        }
+        // A payment, a P2WSH output whose 50 BTC wrapped to a plausible
+        // 705 032 704 sats, and an amount that wrapped below zero.
+        let p2wsh = format!("0020{}", "ab".repeat(32));
+        conn.batch_execute(&format!(
+            "INSERT INTO utxos VALUES ('{TXID}', 0, 1000, '0014ab', true, true, '{TXID}', 9);
+             INSERT INTO utxos VALUES ('{TXID}', 1, 705032704, '{p2wsh}', true, false, NULL, 9);
+             INSERT INTO utxos VALUES ('{TXID}', 2, -1294967296, '0014ab', true, false, NULL, 9);
+             INSERT INTO mempool_tx VALUES ('{TXID}', '2026-10-19 00:00:00');
+             INSERT INTO indexer_state VALUES (0, 10);"
+        ))
+        .unwrap();
+
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+
+        let stored = utxos::table
+            .order(utxos::vout)
+            .load::<Utxo>(&mut conn)
+            .unwrap();
+        assert_eq!(stored.len(), 3);
+        assert_eq!(hex::encode(&stored[0].txid), TXID);
+        assert_eq!(stored[0].value, 1000);
+        assert_eq!(stored[0].script_pubkey, vec![0x00, 0x14, 0xab]);
+        assert_eq!(stored[0].spent_by(), TXID.parse().ok());
+        assert_eq!(hex::encode(&stored[1].script_pubkey), p2wsh);
+        assert_eq!(stored[1].spent_by_txid, None);
+
+        // Only the possibly wrapped amounts are re-fetched.
+        let flagged = utxo_rechecks::table
+            .select(utxo_rechecks::vout)
+            .order(utxo_rechecks::vout)
+            .load::<i32>(&mut conn)
+            .unwrap();
+        assert_eq!(flagged, vec![1, 2]);
+
+        // Nothing is rescanned.
+        let next_height = indexer_state::table
+            .select(indexer_state::next_height)
+            .first::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(next_height, 10);
+        let mempool = mempool_tx::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(mempool, 1);
+    }
+
+    #[test]
+    fn test_retention_migration_reverts_and_reapplies() {
+        use crate::db::schema::watched_outpoints;
+
+        let mut conn = memory_db();
+        let outpoint = OutPoint::new(TXID.parse().unwrap(), 1);
+        diesel::insert_into(watched_outpoints::table)
+            .values(&WatchedOutpoint::from(&outpoint))
+            .execute(&mut conn)
+            .unwrap();
+
+        // Back to hex txids, before the retention policy.
+        let applied = conn.applied_migrations().unwrap().len();
+        for _ in 4..applied {
+            conn.revert_last_migration(crate::MIGRATIONS).unwrap();
+        }
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+
+        let watched = watched_outpoints::table
+            .select(watched_outpoints::txid)
+            .first::<Vec<u8>>(&mut conn)
+            .unwrap();
+        assert_eq!(txid_from_bytes(&watched), Some(outpoint.txid));
+    }
+
+    #[test]
+    fn test_migration_folds_stale_flag_into_state() {
+        use crate::db::schema::servers;
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        // Every migration before the maker lifecycle one.
+        for _ in 0..10 {
+            conn.run_next_migration(crate::MIGRATIONS).unwrap();
+        }
+        conn.batch_execute(
+            "INSERT INTO servers VALUES ('a.onion:6102', 0, true, 'active', NULL, NULL);
+             INSERT INTO servers VALUES ('b.onion:6102', 0, false, 'active', NULL, NULL);",
+        )
+        .unwrap();
+
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+
+        let stored = servers::table
+            .select((servers::onion_address, servers::state, servers::failures))
+            .order(servers::onion_address)
+            .load::<(Option<String>, String, i32)>(&mut conn)
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                (Some("a.onion:6102".to_string()), "stale".to_string(), 0),
+                (Some("b.onion:6102".to_string()), "active".to_string(), 0),
+            ]
+        );
+    }
+}
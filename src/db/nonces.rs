@@ -0,0 +1,59 @@
+//! Nonces of signed maker requests, kept so a request cannot be replayed
+//! while its timestamp is still accepted, including across restarts. Nonces
+//! are scoped to the bond that signed the request.
+
+use std::collections::HashMap;
+
+use bitcoincore_rpc::bitcoin::OutPoint;
+use chrono::{DateTime, Utc};
+use diesel::{SqliteConnection, prelude::*};
+
+use crate::db::{
+    model::{txid_from_bytes, txid_to_bytes},
+    schema::used_nonces,
+};
+
+/// Nonces used since `since` with the bond they were used for, and the time
+/// they were used.
+pub(crate) fn load(
+    conn: &mut SqliteConnection,
+    since: DateTime<Utc>,
+) -> QueryResult<HashMap<(OutPoint, u64), DateTime<Utc>>> {
+    Ok(used_nonces::table
+        .filter(used_nonces::used_at.ge(since.naive_utc()))
+        .select((
+            used_nonces::bond_txid,
+            used_nonces::bond_vout,
+            used_nonces::nonce,
+            used_nonces::used_at,
+        ))
+        .load::<(Vec<u8>, i32, i64, chrono::NaiveDateTime)>(conn)?
+        .into_iter()
+        .filter_map(|(txid, vout, nonce, used_at)| {
+            let bond = OutPoint::new(txid_from_bytes(&txid)?, vout as u32);
+            Some(((bond, nonce as u64), used_at.and_utc()))
+        })
+        .collect())
+}
+
+/// Records `nonce` as used for `bond` at `at` and forgets nonces used before
+/// `expired`.
+pub(crate) fn record(
+    conn: &mut SqliteConnection,
+    bond: &OutPoint,
+    nonce: u64,
+    at: DateTime<Utc>,
+    expired: DateTime<Utc>,
+) -> QueryResult<()> {
+    diesel::delete(used_nonces::table.filter(used_nonces::used_at.lt(expired.naive_utc())))
+        .execute(conn)?;
+    diesel::replace_into(used_nonces::table)
+        .values((
+            used_nonces::bond_txid.eq(txid_to_bytes(&bond.txid)),
+            used_nonces::bond_vout.eq(bond.vout as i32),
+            used_nonces::nonce.eq(nonce as i64),
+            used_nonces::used_at.eq(at.naive_utc()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Offers fetched from makers during liveness probes, one per maker.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use diesel::{SqliteConnection, prelude::*};
+
+use crate::{
+    db::{model::CachedOffer, schema::maker_offers},
+    types::Offer,
+};
+
+/// Replaces the cached offer of `address` with `offer`, fetched at `at`.
+pub(crate) fn store(
+    conn: &mut SqliteConnection,
+    address: &str,
+    offer: &Offer,
+    at: DateTime<Utc>,
+) -> QueryResult<usize> {
+    diesel::replace_into(maker_offers::table)
+        .values(&CachedOffer::new(address, offer, at.naive_utc()))
+        .execute(conn)
+}
+
+/// Every cached offer with the time it was fetched, by maker address.
+pub(crate) fn load(
+    conn: &mut SqliteConnection,
+) -> QueryResult<HashMap<String, (Offer, DateTime<Utc>)>> {
+    Ok(maker_offers::table
+        .load::<CachedOffer>(conn)?
+        .into_iter()
+        .map(|row| {
+            let offer = row.offer();
+            (row.onion_address, (offer, row.fetched_at.and_utc()))
+        })
+        .collect())
+}
+
+maker_rows!(maker_offers);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{memory_db, offer};
+    use crate::types::OfferFilter;
+    use bitcoincore_rpc::bitcoin::Amount;
+
+    #[test]
+    fn test_offer_cache_and_filter() {
+        let mut conn = memory_db();
+
+        let now = Utc::now();
+        store(&mut conn, "a:1", &offer(1_000, 10_000, 1_000_000), now).unwrap();
+        store(&mut conn, "b:1", &offer(500, 50_000, 200_000), now).unwrap();
+        // A newer offer replaces the cached one.
+        store(&mut conn, "b:1", &offer(800, 50_000, 200_000), now).unwrap();
+
+        let offers = load(&mut conn).unwrap();
+        assert_eq!(offers.len(), 2);
+        assert_eq!(offers["b:1"].0, offer(800, 50_000, 200_000));
+        assert_eq!(offers["b:1"].1.timestamp(), now.timestamp());
+
+        let matching = |filter: OfferFilter| {
+            let mut addresses: Vec<_> = offers
+                .iter()
+                .filter(|(_, (offer, _))| filter.matches(offer))
+                .map(|(address, _)| address.as_str())
+                .collect();
+            addresses.sort();
+            addresses
+        };
+        assert_eq!(matching(OfferFilter::default()), ["a:1", "b:1"]);
+        let size = |sats| OfferFilter {
+            amount: Some(Amount::from_sat(sats)),
+            ..Default::default()
+        };
+        assert_eq!(matching(size(20_000)), ["a:1"]);
+        assert_eq!(matching(size(200_000)), ["a:1", "b:1"]);
+        assert!(matching(size(2_000_000)).is_empty());
+        let fee = OfferFilter {
+            max_base_fee: Some(Amount::from_sat(900)),
+            ..Default::default()
+        };
+        assert_eq!(matching(fee), ["b:1"]);
+        let relative = OfferFilter {
+            max_amount_relative_fee_pct: Some(0.05),
+            ..Default::default()
+        };
+        assert!(matching(relative).is_empty());
+
+        rename(&mut conn, "b:1", "c:1").unwrap();
+        delete(&mut conn, "a:1").unwrap();
+        let offers = load(&mut conn).unwrap();
+        assert_eq!(offers.keys().collect::<Vec<_>>(), vec!["c:1"]);
+    }
+}
@@ -0,0 +1,196 @@
+//! Operator policy deciding which active makers are handed to takers.
+//!
+//! The policy is read from a JSON file, for example:
+//!
+//! ```json
+//! {
+//!   "min_bond_sats": 1000000,
+//!   "min_remaining_locktime": 4320,
+//!   "min_confirmations": 6,
+//!   "min_uptime_secs": 3600,
+//!   "allow": [],
+//!   "deny": ["<address>", "<txid>:<vout>"]
+//! }
+//! ```
+//!
+//! Every field is optional. Makers without a bond only pass when no bond
+//! minimum is set.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::TrackerError, types::ServerInfo};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListingPolicy {
+    /// Minimum bond value, in satoshis.
+    pub min_bond_sats: u64,
+    /// Minimum number of blocks until the bond can be swept.
+    pub min_remaining_locktime: u32,
+    /// Minimum confirmations of the bond output.
+    pub min_confirmations: u32,
+    /// Minimum time the maker has been answering pings without a gap.
+    pub min_uptime_secs: u64,
+    /// If non-empty, only these addresses or bond outpoints are listed.
+    pub allow: HashSet<String>,
+    /// Addresses or bond outpoints that are never listed.
+    pub deny: HashSet<String>,
+}
+
+/// Chain tip as last reported by the indexer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tip {
+    pub height: u64,
+    pub median_time: u64,
+}
+
+impl ListingPolicy {
+    pub fn load(path: &Path) -> Result<Self, TrackerError> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| {
+            TrackerError::General(format!("Invalid policy file {}: {e}", path.display()))
+        })
+    }
+
+    /// Whether `info` may be listed. Bond depth and locktime checks fail while
+    /// the tip is still unknown.
+    pub fn allows(&self, address: &str, info: &ServerInfo, tip: Option<Tip>) -> bool {
+        let outpoint = info.bond.as_ref().map(|bond| bond.outpoint.to_string());
+        let listed = |set: &HashSet<String>| {
+            set.contains(address) || outpoint.as_ref().is_some_and(|o| set.contains(o))
+        };
+        if listed(&self.deny) || (!self.allow.is_empty() && !listed(&self.allow)) {
+            return false;
+        }
+
+        let uptime = info
+            .up_since
+            .map(|since| since.elapsed())
+            .unwrap_or_default();
+        if uptime < Duration::from_secs(self.min_uptime_secs) {
+            return false;
+        }
+
+        let needs_bond =
+            self.min_bond_sats > 0 || self.min_remaining_locktime > 0 || self.min_confirmations > 0;
+        let Some(bond) = &info.bond else {
+            return !needs_bond;
+        };
+        if bond.amount.to_sat() < self.min_bond_sats {
+            return false;
+        }
+        if self.min_remaining_locktime == 0 && self.min_confirmations == 0 {
+            return true;
+        }
+
+        let Some(tip) = tip else {
+            return false;
+        };
+        let remaining = bond.remaining_locktime(tip.height, tip.median_time);
+        let confirmations = bond
+            .conf_height
+            .map(|height| (tip.height + 1).saturating_sub(height as u64))
+            .unwrap_or_default();
+        remaining >= self.min_remaining_locktime as u64
+            && confirmations >= self.min_confirmations as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{TIP, bond, server},
+        types::FidelityBond,
+    };
+    use bitcoincore_rpc::bitcoin::{Amount, Txid, absolute::LockTime, hashes::Hash};
+    use tokio::time::Instant;
+
+    fn maker(tag: u8, sats: u64, lock_height: u32, conf_height: u32, up: u64) -> ServerInfo {
+        let bond = FidelityBond {
+            amount: Amount::from_sat(sats),
+            lock_time: LockTime::from_height(lock_height).unwrap(),
+            conf_height: Some(conf_height),
+            ..bond(tag)
+        };
+        ServerInfo {
+            up_since: Instant::now().checked_sub(Duration::from_secs(up)),
+            ..server(&format!("maker{tag}:6102"), Some(bond))
+        }
+    }
+
+    /// Makers that each fail exactly one of the thresholds in `strict()`,
+    /// plus one that passes them all and one without a bond.
+    fn registry() -> Vec<ServerInfo> {
+        let mut legacy = maker(6, 0, 0, 0, 7_200);
+        legacy.bond = None;
+        vec![
+            maker(0, 5_000_000, 910_000, 899_000, 7_200),
+            maker(1, 500_000, 910_000, 899_000, 7_200),
+            maker(2, 5_000_000, 901_000, 899_000, 7_200),
+            maker(3, 5_000_000, 910_000, 899_999, 7_200),
+            maker(4, 5_000_000, 910_000, 899_000, 60),
+            maker(5, 5_000_000, 910_000, 899_000, 7_200),
+            legacy,
+        ]
+    }
+
+    fn strict() -> ListingPolicy {
+        ListingPolicy {
+            min_bond_sats: 1_000_000,
+            min_remaining_locktime: 4_320,
+            min_confirmations: 6,
+            min_uptime_secs: 3_600,
+            allow: HashSet::new(),
+            deny: HashSet::from([format!("{}:0", Txid::from_byte_array([5; 32]))]),
+        }
+    }
+
+    fn listed(policy: &ListingPolicy, tip: Option<Tip>) -> Vec<String> {
+        registry()
+            .into_iter()
+            .filter(|info| policy.allows(&info.onion_address, info, tip))
+            .map(|info| info.onion_address)
+            .collect()
+    }
+
+    #[test]
+    fn test_default_policy_lists_everyone() {
+        assert_eq!(listed(&ListingPolicy::default(), None).len(), 7);
+    }
+
+    #[test]
+    fn test_strict_policy_filters_fixture_registry() {
+        assert_eq!(listed(&strict(), Some(TIP)), vec!["maker0:6102"]);
+        // Depth and locktime cannot be judged before the first tip.
+        assert!(listed(&strict(), None).is_empty());
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = ListingPolicy {
+            allow: HashSet::from(["maker3:6102".to_string(), "maker6:6102".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(listed(&policy, None), vec!["maker3:6102", "maker6:6102"]);
+    }
+
+    #[test]
+    fn test_load_policy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        std::fs::write(
+            &path,
+            r#"{ "min_bond_sats": 1000, "deny": ["maker1:6102"] }"#,
+        )
+        .unwrap();
+        let policy = ListingPolicy::load(&path).unwrap();
+        assert_eq!(policy.min_bond_sats, 1_000);
+        assert!(policy.deny.contains("maker1:6102"));
+
+        std::fs::write(&path, r#"{ "min_bond": 1000 }"#).unwrap();
+        assert!(ListingPolicy::load(&path).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Liveness probe history and its rollup into uptime ratios and latency.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{
+    QueryableByName, SqliteConnection,
+    prelude::*,
+    sql_query,
+    sql_types::{Double, Nullable, Text, Timestamp},
+};
+use serde::Serialize;
+
+use crate::{db::model::MakerProbe, db::schema::maker_probes, types::Uptime};
+
+/// Probes older than the longest rollup window are pruned.
+pub(crate) const PROBE_HISTORY: TimeDelta = TimeDelta::days(7);
+
+/// Rolled-up probe results of one maker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ProbeStats {
+    pub uptime: Uptime,
+    /// Mean round-trip time of the pings answered in the last 24 hours.
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(QueryableByName)]
+struct Rollup {
+    #[diesel(sql_type = Text)]
+    onion_address: String,
+    #[diesel(sql_type = Nullable<Double>)]
+    hour: Option<f64>,
+    #[diesel(sql_type = Nullable<Double>)]
+    day: Option<f64>,
+    #[diesel(sql_type = Nullable<Double>)]
+    week: Option<f64>,
+    #[diesel(sql_type = Nullable<Double>)]
+    latency_ms: Option<f64>,
+}
+
+/// Stores the outcome of probing `address` at `at`: the round-trip time, or
+/// the kind of error the probe failed with.
+pub(crate) fn record(
+    conn: &mut SqliteConnection,
+    address: &str,
+    at: DateTime<Utc>,
+    outcome: &Result<Duration, String>,
+) -> QueryResult<usize> {
+    let row = MakerProbe {
+        onion_address: address.to_string(),
+        probed_at: at.naive_utc(),
+        success: outcome.is_ok(),
+        latency_ms: outcome.as_ref().ok().map(|rtt| rtt.as_millis() as i64),
+        error_kind: outcome.as_ref().err().cloned(),
+    };
+    diesel::insert_into(maker_probes::table)
+        .values(&row)
+        .execute(conn)
+}
+
+/// Uptime over the last hour, day and week, and recent latency, per maker
+/// probed within the last week.
+pub(crate) fn rollups(
+    conn: &mut SqliteConnection,
+    now: DateTime<Utc>,
+) -> QueryResult<HashMap<String, ProbeStats>> {
+    let since = |window: TimeDelta| (now - window).naive_utc();
+    let rows = sql_query(
+        "SELECT onion_address, \
+             AVG(CASE WHEN probed_at >= ?1 THEN success END) AS hour, \
+             AVG(CASE WHEN probed_at >= ?2 THEN success END) AS day, \
+             AVG(success) AS week, \
+             AVG(CASE WHEN probed_at >= ?2 AND success THEN latency_ms END) AS latency_ms \
+         FROM maker_probes WHERE probed_at >= ?3 GROUP BY onion_address",
+    )
+    .bind::<Timestamp, _>(since(TimeDelta::hours(1)))
+    .bind::<Timestamp, _>(since(TimeDelta::days(1)))
+    .bind::<Timestamp, _>(since(PROBE_HISTORY))
+    .load::<Rollup>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let stats = ProbeStats {
+                uptime: Uptime {
+                    hour: row.hour,
+                    day: row.day,
+                    week: row.week,
+                },
+                latency_ms: row.latency_ms.map(|ms| ms.round() as u64),
+            };
+            (row.onion_address, stats)
+        })
+        .collect())
+}
+
+maker_rows!(maker_probes);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::memory_db;
+
+    #[test]
+    fn test_rollups_by_window() {
+        let mut conn = memory_db();
+
+        let now = Utc::now();
+        let ok = |ms| Ok(Duration::from_millis(ms));
+        let failed = Err("TimedOut".to_string());
+        let history = [
+            // Within the hour: 1 of 2 answered.
+            (TimeDelta::minutes(10), ok(100)),
+            (TimeDelta::minutes(20), failed.clone()),
+            // Within the day: 3 of 4.
+            (TimeDelta::hours(5), ok(300)),
+            (TimeDelta::hours(6), ok(200)),
+            // Within the week: 3 of 6.
+            (TimeDelta::days(3), failed.clone()),
+            (TimeDelta::days(4), failed.clone()),
+            // Beyond the longest window.
+            (TimeDelta::days(8), ok(1_000)),
+        ];
+        for (age, outcome) in &history {
+            record(&mut conn, "a:1", now - *age, outcome).unwrap();
+        }
+        record(&mut conn, "b:1", now - TimeDelta::days(2), &ok(50)).unwrap();
+
+        let stats = rollups(&mut conn, now).unwrap();
+        assert_eq!(
+            stats["a:1"],
+            ProbeStats {
+                uptime: Uptime {
+                    hour: Some(0.5),
+                    day: Some(0.75),
+                    week: Some(0.5),
+                },
+                latency_ms: Some(200),
+            }
+        );
+        assert_eq!(
+            stats["b:1"],
+            ProbeStats {
+                uptime: Uptime {
+                    hour: None,
+                    day: None,
+                    week: Some(1.0),
+                },
+                latency_ms: None,
+            }
+        );
+
+        rename(&mut conn, "b:1", "c:1").unwrap();
+        delete(&mut conn, "a:1").unwrap();
+        let stats = rollups(&mut conn, now).unwrap();
+        assert_eq!(stats.keys().collect::<Vec<_>>(), vec!["c:1"]);
+    }
+}
@@ -0,0 +1,479 @@
+use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
+
+use bitcoincore_rpc::bitcoin::{OutPoint, Txid};
+
+use chrono::{TimeDelta, Utc};
+use diesel::{
+    QueryableByName, SqliteConnection,
+    dsl::{exists, not},
+    prelude::*,
+    r2d2::ConnectionManager,
+    sql_query,
+    sql_types::BigInt,
+};
+use r2d2::Pool;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use crate::{
+    db::{
+        model::txid_to_bytes,
+        probes::PROBE_HISTORY,
+        schema::{
+            fidelity_bonds, indexer_state, maker_probes, mempool_inputs, mempool_tx, utxos,
+            watched_outpoints,
+        },
+    },
+    error::TrackerError,
+    handle_result,
+    status::{self, State, Status},
+    types::DbRequest,
+};
+
+/// How long spent outputs and mempool entries are kept around.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Blocks to keep a spent output once its spend has confirmed.
+    /// `None` keeps spent outputs forever.
+    pub spent_depth: Option<u32>,
+    /// Mempool transactions first seen longer ago than this are dropped.
+    pub mempool_max_age: Duration,
+    /// Time between two pruning runs.
+    pub interval: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            spent_depth: None,
+            // Same as bitcoind's default `-mempoolexpiry`.
+            mempool_max_age: Duration::from_secs(336 * 60 * 60),
+            interval: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Outputs per `DELETE`, to stay below SQLite's bound parameter limit.
+const DELETE_CHUNK_SIZE: usize = 500;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub spent_outputs: usize,
+    /// Unconfirmed outputs of transactions that left the mempool.
+    pub orphaned_outputs: usize,
+    pub mempool_txs: usize,
+    /// Registered bonds whose mempool spends expired without confirming.
+    pub released_bonds: Vec<OutPoint>,
+    pub probes: usize,
+    pub reclaimed_bytes: u64,
+}
+
+#[derive(QueryableByName)]
+struct PragmaValue {
+    #[diesel(sql_type = BigInt)]
+    value: i64,
+}
+
+pub async fn run(
+    pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
+    db_tx: Sender<DbRequest>,
+    policy: RetentionPolicy,
+    status_tx: status::Sender,
+) {
+    info!("Pruner started with {:?}", policy);
+    loop {
+        tokio::time::sleep(policy.interval).await;
+        let mut conn = handle_result!(status_tx, pool.get());
+        let report = handle_result!(status_tx, prune(&mut conn, &policy));
+        info!("Pruning finished: {:?}", report);
+        if !report.released_bonds.is_empty() {
+            let db_request = DbRequest::ReinstateBonds(report.released_bonds.clone());
+            handle_result!(status_tx, db_tx.send(db_request).await);
+        }
+        let _ = status_tx
+            .send(Status {
+                state: State::Healthy(format!(
+                    "pruned {} spent outputs, {} orphaned outputs, {} mempool transactions and {} probes, reclaimed {} bytes",
+                    report.spent_outputs,
+                    report.orphaned_outputs,
+                    report.mempool_txs,
+                    report.probes,
+                    report.reclaimed_bytes
+                )),
+            })
+            .await;
+    }
+}
+
+/// Deletes spent outputs that are deeper than the retention depth, expired
+/// mempool entries, unconfirmed outputs of transactions no longer in the
+/// mempool and probes older than the longest rollup window. Outputs that are
+/// still watched or back a registered fidelity bond are never deleted.
+/// Mempool entries of confirmed transactions are removed by the indexer.
+/// Registered bonds spent only by the expired mempool entries, and whose
+/// spend did not confirm, are marked unspent again and reported as released.
+pub fn prune(
+    conn: &mut SqliteConnection,
+    policy: &RetentionPolicy,
+) -> Result<PruneReport, TrackerError> {
+    let page_size = pragma(conn, "page_size")?;
+    let free_pages_before = pragma(conn, "freelist_count")?;
+
+    let mempool_max_age = TimeDelta::from_std(policy.mempool_max_age)
+        .map_err(|e| TrackerError::General(e.to_string()))?;
+    let seen_before = Utc::now().naive_utc() - mempool_max_age;
+
+    let probed_before = (Utc::now() - PROBE_HISTORY).naive_utc();
+
+    let (spent_outputs, orphaned_outputs, mempool_txs, released_bonds, probes) =
+        conn.transaction::<_, TrackerError, _>(|conn| {
+            let next_height = indexer_state::table
+                .select(indexer_state::next_height)
+                .first::<i64>(conn)
+                .optional()?;
+
+            let watched = watched_outpoints::table.filter(
+                watched_outpoints::txid
+                    .eq(utxos::txid)
+                    .and(watched_outpoints::vout.eq(utxos::vout)),
+            );
+            let bonded = fidelity_bonds::table.filter(
+                fidelity_bonds::txid
+                    .eq(utxos::txid)
+                    .and(fidelity_bonds::vout.eq(utxos::vout)),
+            );
+            let spent_outputs = match (policy.spent_depth, next_height) {
+                (Some(depth), Some(next_height)) => {
+                    let cutoff = next_height - 1 - depth as i64;
+                    diesel::delete(
+                        utxos::table
+                            .filter(utxos::spent.eq(true))
+                            .filter(utxos::spent_height.le(cutoff as i32))
+                            .filter(not(exists(watched)))
+                            .filter(not(exists(bonded))),
+                    )
+                    .execute(conn)?
+                }
+                _ => 0,
+            };
+
+            let expired = mempool_tx::table
+                .filter(mempool_tx::seen_at.lt(seen_before))
+                .select(mempool_tx::txid);
+            let expired_spends: HashSet<(String, i32)> = mempool_inputs::table
+                .filter(mempool_inputs::txid.eq_any(expired))
+                .select((mempool_inputs::input_txid, mempool_inputs::input_vout))
+                .load(conn)?
+                .into_iter()
+                .collect();
+            diesel::delete(mempool_inputs::table.filter(mempool_inputs::txid.eq_any(expired)))
+                .execute(conn)?;
+            let mempool_txs =
+                diesel::delete(mempool_tx::table.filter(mempool_tx::seen_at.lt(seen_before)))
+                    .execute(conn)?;
+
+            // A bond stays spent while another mempool transaction spends it
+            // or once its spend confirmed. Bonds without a stored output
+            // cannot be told apart from confirmed spends and stay spent.
+            let bonds: HashSet<(Vec<u8>, i32)> = fidelity_bonds::table
+                .select((fidelity_bonds::txid, fidelity_bonds::vout))
+                .load(conn)?
+                .into_iter()
+                .collect();
+            let mut released_bonds = Vec::new();
+            for (txid, vout) in expired_spends {
+                let Ok(parsed) = Txid::from_str(&txid) else {
+                    continue;
+                };
+                let bytes = txid_to_bytes(&parsed);
+                if !bonds.contains(&(bytes.clone(), vout)) {
+                    continue;
+                }
+                let respent = diesel::select(exists(
+                    mempool_inputs::table
+                        .filter(mempool_inputs::input_txid.eq(&txid))
+                        .filter(mempool_inputs::input_vout.eq(vout)),
+                ))
+                .get_result::<bool>(conn)?;
+                let spent_height = utxos::table
+                    .find((&bytes, vout))
+                    .select(utxos::spent_height)
+                    .first::<Option<i32>>(conn)
+                    .optional()?;
+                if respent || spent_height != Some(None) {
+                    continue;
+                }
+                // The output is unspent again, so the bond verifies again.
+                diesel::update(utxos::table.find((&bytes, vout)))
+                    .set((
+                        utxos::spent.eq(false),
+                        utxos::spent_by_txid.eq(None::<Vec<u8>>),
+                        utxos::confirmed.eq(utxos::block_height.is_not_null()),
+                    ))
+                    .execute(conn)?;
+                released_bonds.push(OutPoint::new(parsed, vout as u32));
+            }
+
+            // Outputs are confirmed along with their transaction, so an unconfirmed
+            // one whose transaction is no longer tracked in the mempool will never
+            // confirm.
+            let in_mempool: HashSet<Vec<u8>> = mempool_tx::table
+                .select(mempool_tx::txid)
+                .load::<String>(conn)?
+                .iter()
+                .filter_map(|txid| Txid::from_str(txid).ok())
+                .map(|txid| txid_to_bytes(&txid))
+                .collect();
+            let orphaned: Vec<Vec<u8>> = utxos::table
+                .filter(utxos::block_height.is_null())
+                .select(utxos::txid)
+                .distinct()
+                .load::<Vec<u8>>(conn)?
+                .into_iter()
+                .filter(|txid| !in_mempool.contains(txid))
+                .collect();
+            let mut orphaned_outputs = 0;
+            for chunk in orphaned.chunks(DELETE_CHUNK_SIZE) {
+                orphaned_outputs += diesel::delete(
+                    utxos::table
+                        .filter(utxos::txid.eq_any(chunk))
+                        .filter(utxos::block_height.is_null())
+                        .filter(not(exists(watched)))
+                        .filter(not(exists(bonded))),
+                )
+                .execute(conn)?;
+            }
+
+            let probes = diesel::delete(
+                maker_probes::table.filter(maker_probes::probed_at.lt(probed_before)),
+            )
+            .execute(conn)?;
+
+            Ok((
+                spent_outputs,
+                orphaned_outputs,
+                mempool_txs,
+                released_bonds,
+                probes,
+            ))
+        })?;
+
+    let free_pages_after = pragma(conn, "freelist_count")?;
+
+    Ok(PruneReport {
+        spent_outputs,
+        orphaned_outputs,
+        mempool_txs,
+        released_bonds,
+        probes,
+        reclaimed_bytes: (free_pages_after - free_pages_before).max(0) as u64 * page_size as u64,
+    })
+}
+
+fn pragma(conn: &mut SqliteConnection, name: &str) -> Result<i64, TrackerError> {
+    let row = sql_query(format!("SELECT {name} AS value FROM pragma_{name}"))
+        .get_result::<PragmaValue>(conn)?;
+    Ok(row.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::model::{Bond, IndexerState, MempoolInput, MempoolTx, Utxo, WatchedOutpoint};
+    use crate::test_utils::{self, memory_db};
+    use crate::types::FidelityBond;
+    use bitcoincore_rpc::bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid, hashes::Hash};
+
+    fn spent_utxo(tag: u8, spent_height: Option<i32>) -> Utxo {
+        let outpoint = OutPoint::new(Txid::from_byte_array([tag; 32]), 0);
+        let tx_out = TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey: ScriptBuf::new(),
+        };
+        Utxo {
+            spent: spent_height.is_some(),
+            spent_height,
+            ..Utxo::new(&outpoint, &tx_out, Some(1))
+        }
+    }
+
+    #[test]
+    fn test_prune_keeps_recent_and_watched_outputs() {
+        let mut conn = memory_db();
+
+        let deep = spent_utxo(1, Some(80));
+        let recent = spent_utxo(2, Some(95));
+        let watched = spent_utxo(3, Some(50));
+        let unspent = spent_utxo(4, None);
+        diesel::insert_into(utxos::table)
+            .values(&vec![deep, recent, watched, unspent])
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(watched_outpoints::table)
+            .values(&WatchedOutpoint::from(&OutPoint::new(
+                Txid::from_byte_array([3; 32]),
+                0,
+            )))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(indexer_state::table)
+            .values(&IndexerState {
+                id: 0,
+                next_height: 101,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(mempool_tx::table)
+            .values(&vec![
+                MempoolTx {
+                    txid: "old".to_string(),
+                    seen_at: now - TimeDelta::days(30),
+                },
+                MempoolTx {
+                    txid: "new".to_string(),
+                    seen_at: now,
+                },
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(mempool_inputs::table)
+            .values(&MempoolInput {
+                txid: "old".to_string(),
+                input_txid: "prev".to_string(),
+                input_vout: 0,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let policy = RetentionPolicy {
+            spent_depth: Some(10),
+            ..Default::default()
+        };
+        crate::db::probes::record(
+            &mut conn,
+            "a:1",
+            Utc::now() - TimeDelta::days(8),
+            &Err("TimedOut".to_string()),
+        )
+        .unwrap();
+
+        let report = prune(&mut conn, &policy).unwrap();
+        assert_eq!(report.spent_outputs, 1);
+        assert_eq!(report.mempool_txs, 1);
+        assert_eq!(report.probes, 1);
+
+        let remaining = utxos::table.count().get_result::<i64>(&mut conn).unwrap();
+        assert_eq!(remaining, 3);
+        let inputs = mempool_inputs::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(inputs, 0);
+    }
+
+    #[test]
+    fn test_prune_orphaned_outputs() {
+        let mut conn = memory_db();
+
+        let unconfirmed = |tag: u8| Utxo {
+            confirmed: false,
+            block_height: None,
+            ..spent_utxo(tag, None)
+        };
+        let pending = unconfirmed(1);
+        let dropped = unconfirmed(2);
+        let watched = unconfirmed(3);
+        let confirmed = spent_utxo(4, None);
+        diesel::insert_into(utxos::table)
+            .values(&vec![pending, dropped, watched, confirmed])
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(watched_outpoints::table)
+            .values(&WatchedOutpoint::from(&OutPoint::new(
+                Txid::from_byte_array([3; 32]),
+                0,
+            )))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(mempool_tx::table)
+            .values(&MempoolTx {
+                txid: Txid::from_byte_array([1; 32]).to_string(),
+                seen_at: Utc::now().naive_utc(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        let report = prune(&mut conn, &RetentionPolicy::default()).unwrap();
+        assert_eq!(report.orphaned_outputs, 1);
+        let remaining: Vec<Vec<u8>> = utxos::table
+            .select(utxos::txid)
+            .order(utxos::txid)
+            .load(&mut conn)
+            .unwrap();
+        let txid = |tag: u8| txid_to_bytes(&Txid::from_byte_array([tag; 32]));
+        assert_eq!(remaining, vec![txid(1), txid(3), txid(4)]);
+    }
+
+    #[test]
+    fn test_prune_releases_bonds_of_expired_spends() {
+        let mut conn = memory_db();
+
+        // Bond 1 is spent by an expired transaction only, bond 2 also by a
+        // recent replacement, and the expired spend of bond 3 confirmed.
+        let utxos = vec![
+            spent_utxo(1, None),
+            spent_utxo(2, None),
+            spent_utxo(3, Some(90)),
+        ];
+        diesel::insert_into(utxos::table)
+            .values(&utxos)
+            .execute(&mut conn)
+            .unwrap();
+        for tag in 1..=3 {
+            let bond = FidelityBond {
+                outpoint: OutPoint::new(Txid::from_byte_array([tag; 32]), 0),
+                ..test_utils::bond(tag)
+            };
+            diesel::insert_into(fidelity_bonds::table)
+                .values(&Bond::new(&format!("{tag}.onion:6102"), &bond, None))
+                .execute(&mut conn)
+                .unwrap();
+        }
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(mempool_tx::table)
+            .values(&vec![
+                MempoolTx {
+                    txid: "old".to_string(),
+                    seen_at: now - TimeDelta::days(30),
+                },
+                MempoolTx {
+                    txid: "replacement".to_string(),
+                    seen_at: now,
+                },
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        let input = |txid: &str, tag: u8| MempoolInput {
+            txid: txid.to_string(),
+            input_txid: Txid::from_byte_array([tag; 32]).to_string(),
+            input_vout: 0,
+        };
+        diesel::insert_into(mempool_inputs::table)
+            .values(&vec![
+                input("old", 1),
+                input("old", 2),
+                input("old", 3),
+                input("replacement", 2),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+
+        let report = prune(&mut conn, &RetentionPolicy::default()).unwrap();
+        assert_eq!(
+            report.released_bonds,
+            vec![OutPoint::new(Txid::from_byte_array([1; 32]), 0)]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Weighted random sampling of makers for `GetSample`.
+//!
+//! Each maker is weighted by the value of its fidelity bond, so takers spread
+//! over the network in proportion to what makers have locked up instead of
+//! all picking the same ones, and a single query no longer reveals every
+//! maker.
+
+use rand::Rng;
+
+use crate::{
+    db::policy::Tip,
+    types::{FidelityBond, ServerInfo},
+};
+
+/// Most makers returned by a single sample.
+pub const MAX_SAMPLE_SIZE: usize = 32;
+
+/// Yearly interest rate of the bond value formula.
+const BOND_INTEREST_RATE: f64 = 0.015;
+
+/// Blocks per year at the target block interval.
+const BLOCKS_PER_YEAR: f64 = 52_560.0;
+
+/// Value of `bond` at `tip`: `(amount * (e^(r * years_left) - 1))^2`, with
+/// the amount in BTC. Squaring makes one large bond worth more than the same
+/// coins split over several makers. Zero once the bond can be swept.
+pub(crate) fn bond_score(bond: &FidelityBond, tip: Tip) -> f64 {
+    let years = bond.remaining_locktime(tip.height, tip.median_time) as f64 / BLOCKS_PER_YEAR;
+    (bond.amount.to_btc() * (BOND_INTEREST_RATE * years).exp_m1()).powi(2)
+}
+
+/// Draws up to `count` distinct makers, each with probability proportional
+/// to its bond score (Efraimidis-Spirakis weighted sampling without
+/// replacement). Makers scoring zero are drawn uniformly once every scored
+/// maker has been taken. With an unknown tip every maker weighs the same.
+///
+/// Makers are visited in address order, so a seeded `rng` gives the same
+/// sample for the same registry.
+pub(crate) fn sample<'a, R: Rng>(
+    makers: impl IntoIterator<Item = (&'a String, &'a ServerInfo)>,
+    count: usize,
+    tip: Option<Tip>,
+    rng: &mut R,
+) -> Vec<String> {
+    let mut makers: Vec<_> = makers.into_iter().collect();
+    makers.sort_by(|a, b| a.0.cmp(b.0));
+    let mut keyed: Vec<((bool, f64), &String)> = makers
+        .into_iter()
+        .map(|(address, info)| {
+            let weight = match (tip, &info.bond) {
+                (None, _) => 1.0,
+                (Some(tip), Some(bond)) => bond_score(bond, tip),
+                (Some(_), None) => 0.0,
+            };
+            // Sorting by u^(1/w) picks each maker with probability w / sum(w);
+            // its logarithm keeps large weights from rounding to 1.
+            let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+            let key = if weight > 0.0 {
+                (true, u.ln() / weight)
+            } else {
+                (false, u)
+            };
+            (key, address)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    keyed
+        .into_iter()
+        .take(count.min(MAX_SAMPLE_SIZE))
+        .map(|(_, address)| address.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TIP, bond, server};
+    use bitcoincore_rpc::bitcoin::{Amount, absolute::LockTime};
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::collections::HashMap;
+
+    fn maker(tag: u8, sats: u64, lock_height: u32) -> (String, ServerInfo) {
+        let address = format!("maker{tag}:6102");
+        let bond = FidelityBond {
+            amount: Amount::from_sat(sats),
+            lock_time: LockTime::from_height(lock_height).unwrap(),
+            conf_height: Some(899_000),
+            ..bond(tag)
+        };
+        let info = server(&address, Some(bond));
+        (address, info)
+    }
+
+    fn registry() -> Vec<(String, ServerInfo)> {
+        let mut legacy = maker(4, 0, 0);
+        legacy.1.bond = None;
+        vec![
+            maker(0, 1_000_000, 952_560),
+            maker(1, 2_000_000, 952_560),
+            maker(2, 4_000_000, 952_560),
+            maker(3, 4_000_000, 926_280),
+            legacy,
+        ]
+    }
+
+    fn draw(
+        makers: &[(String, ServerInfo)],
+        count: usize,
+        tip: Option<Tip>,
+        seed: u64,
+    ) -> Vec<String> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        sample(makers.iter().map(|(a, i)| (a, i)), count, tip, &mut rng)
+    }
+
+    #[test]
+    fn test_bond_score() {
+        let makers = registry();
+        let score = |i: usize| bond_score(makers[i].1.bond.as_ref().unwrap(), TIP);
+        // Score grows with the square of the amount.
+        assert!((score(1) / score(0) - 4.0).abs() < 1e-9);
+        // A shorter remaining lock is worth less.
+        assert!(score(3) < score(2));
+        let past = Tip {
+            height: 960_000,
+            median_time: 0,
+        };
+        assert_eq!(bond_score(makers[0].1.bond.as_ref().unwrap(), past), 0.0);
+    }
+
+    #[test]
+    fn test_single_draws_follow_scores() {
+        let makers = registry();
+        let scores: Vec<f64> = makers[..4]
+            .iter()
+            .map(|(_, info)| bond_score(info.bond.as_ref().unwrap(), TIP))
+            .collect();
+        let total: f64 = scores.iter().sum();
+
+        const DRAWS: usize = 20_000;
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut hits: HashMap<String, usize> = HashMap::new();
+        for _ in 0..DRAWS {
+            let picked = sample(makers.iter().map(|(a, i)| (a, i)), 1, Some(TIP), &mut rng);
+            *hits.entry(picked[0].clone()).or_default() += 1;
+        }
+
+        // Each share is within five standard deviations of its expectation.
+        for (i, score) in scores.iter().enumerate() {
+            let p = score / total;
+            let observed = hits.get(&makers[i].0).copied().unwrap_or_default() as f64;
+            let sigma = (DRAWS as f64 * p * (1.0 - p)).sqrt();
+            assert!(
+                (observed - DRAWS as f64 * p).abs() < 5.0 * sigma,
+                "maker {i}: {observed} hits, expected {}",
+                DRAWS as f64 * p
+            );
+        }
+        // The maker without a bond is never drawn while bonded ones remain.
+        assert!(!hits.contains_key(&makers[4].0));
+    }
+
+    #[test]
+    fn test_unknown_tip_samples_uniformly() {
+        let makers = registry();
+        const DRAWS: usize = 10_000;
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut hits: HashMap<String, usize> = HashMap::new();
+        for _ in 0..DRAWS {
+            let picked = sample(makers.iter().map(|(a, i)| (a, i)), 1, None, &mut rng);
+            *hits.entry(picked[0].clone()).or_default() += 1;
+        }
+        let p = 1.0 / makers.len() as f64;
+        let sigma = (DRAWS as f64 * p * (1.0 - p)).sqrt();
+        for (address, _) in &makers {
+            let observed = hits.get(address).copied().unwrap_or_default() as f64;
+            assert!((observed - DRAWS as f64 * p).abs() < 5.0 * sigma);
+        }
+    }
+
+    #[test]
+    fn test_sample_is_distinct_and_reproducible() {
+        let makers = registry();
+        let picked = draw(&makers, 10, Some(TIP), 42);
+        assert_eq!(picked.len(), makers.len());
+        assert_eq!(picked.last().unwrap(), &makers[4].0);
+        let mut unique = picked.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), makers.len());
+
+        assert_eq!(
+            draw(&makers, 3, Some(TIP), 42),
+            draw(&makers, 3, Some(TIP), 42)
+        );
+        assert_eq!(draw(&makers, 2, Some(TIP), 42).len(), 2);
+    }
+
+    #[test]
+    fn test_sample_size_is_capped() {
+        let makers: Vec<_> = (0..40).map(|tag| maker(tag, 1_000_000, 952_560)).collect();
+        assert_eq!(draw(&makers, 100, Some(TIP), 1).len(), MAX_SAMPLE_SIZE);
+    }
+}
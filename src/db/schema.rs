@@ -1,5 +1,72 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    fidelity_bonds (onion_address) {
+        onion_address -> Text,
+        txid -> Binary,
+        vout -> Integer,
+        amount -> BigInt,
+        lock_time -> BigInt,
+        pubkey -> Binary,
+        conf_height -> Nullable<Integer>,
+        cert_expiry -> Nullable<Integer>,
+        cert_hash -> Nullable<Binary>,
+        cert_sig -> Nullable<Binary>,
+    }
+}
+
+diesel::table! {
+    indexer_state (id) {
+        id -> Integer,
+        next_height -> BigInt,
+    }
+}
+
+diesel::table! {
+    maker_offers (onion_address) {
+        onion_address -> Text,
+        base_fee -> BigInt,
+        amount_relative_fee_pct -> Double,
+        time_relative_fee_pct -> Double,
+        required_confirms -> Integer,
+        minimum_locktime -> Integer,
+        min_size -> BigInt,
+        max_size -> BigInt,
+        fetched_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    maker_probes (id) {
+        id -> Integer,
+        onion_address -> Text,
+        probed_at -> Timestamp,
+        success -> Bool,
+        latency_ms -> Nullable<BigInt>,
+        error_kind -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    maker_transitions (id) {
+        id -> Integer,
+        onion_address -> Text,
+        from_state -> Nullable<Text>,
+        to_state -> Text,
+        reason -> Text,
+        at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    maker_versions (onion_address) {
+        onion_address -> Text,
+        software_version -> Text,
+        protocol_versions -> Text,
+        reported_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     mempool_inputs (rowid) {
         rowid -> Integer,
@@ -20,23 +87,64 @@ diesel::table! {
     servers (onion_address) {
         onion_address -> Nullable<Text>,
         cooldown_seconds -> Float,
-        stale -> Bool,
+        state -> Text,
+        first_seen -> Nullable<Timestamp>,
+        last_seen -> Nullable<Timestamp>,
+        failures -> Integer,
+    }
+}
+
+diesel::table! {
+    used_nonces (bond_txid, bond_vout, nonce) {
+        bond_txid -> Binary,
+        bond_vout -> Integer,
+        nonce -> BigInt,
+        used_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    utxo_rechecks (txid, vout) {
+        txid -> Binary,
+        vout -> Integer,
     }
 }
 
 diesel::table! {
     utxos (txid, vout) {
-        txid -> Text,
+        txid -> Binary,
         vout -> Integer,
-        value -> Integer,
-        script_pubkey -> Text,
+        value -> BigInt,
+        script_pubkey -> Binary,
         confirmed -> Bool,
         spent -> Bool,
-        spent_by_txid -> Nullable<Text>,
+        spent_by_txid -> Nullable<Binary>,
         block_height -> Nullable<Integer>,
+        spent_height -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    watched_outpoints (txid, vout) {
+        txid -> Binary,
+        vout -> Integer,
     }
 }
 
 diesel::joinable!(mempool_inputs -> mempool_tx (txid));
 
-diesel::allow_tables_to_appear_in_same_query!(mempool_inputs, mempool_tx, servers, utxos,);
+diesel::allow_tables_to_appear_in_same_query!(
+    fidelity_bonds,
+    indexer_state,
+    maker_offers,
+    maker_probes,
+    maker_transitions,
+    maker_versions,
+    mempool_inputs,
+    mempool_tx,
+    servers,
+    used_nonces,
+    utxo_rechecks,
+    utxos,
+    watched_outpoints,
+);
@@ -0,0 +1,73 @@
+//! Software and protocol versions makers report in their pongs, one entry
+//! per maker.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use diesel::{SqliteConnection, prelude::*};
+
+use crate::{
+    db::{model::ReportedVersion, schema::maker_versions},
+    types::MakerVersion,
+};
+
+/// Replaces the versions of `address` with `version`, reported at `at`.
+pub(crate) fn store(
+    conn: &mut SqliteConnection,
+    address: &str,
+    version: &MakerVersion,
+    at: DateTime<Utc>,
+) -> QueryResult<usize> {
+    diesel::replace_into(maker_versions::table)
+        .values(&ReportedVersion::new(address, version, at.naive_utc()))
+        .execute(conn)
+}
+
+/// Every reported version, by maker address.
+pub(crate) fn load(conn: &mut SqliteConnection) -> QueryResult<HashMap<String, MakerVersion>> {
+    Ok(maker_versions::table
+        .load::<ReportedVersion>(conn)?
+        .into_iter()
+        .map(|row| {
+            let version = row.version();
+            (row.onion_address, version)
+        })
+        .collect())
+}
+
+maker_rows!(maker_versions);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{memory_db, version};
+
+    #[test]
+    fn test_version_store() {
+        let mut conn = memory_db();
+
+        let now = Utc::now();
+        store(&mut conn, "a:1", &version("coinswap/0.1.2", &[1]), now).unwrap();
+        store(&mut conn, "b:1", &version("coinswap/0.1.2", &[]), now).unwrap();
+        // A newer report replaces the stored one.
+        store(&mut conn, "b:1", &version("coinswap/0.2.0", &[1, 2]), now).unwrap();
+
+        let versions = load(&mut conn).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions["a:1"], version("coinswap/0.1.2", &[1]));
+        assert_eq!(versions["b:1"], version("coinswap/0.2.0", &[1, 2]));
+        assert!(versions["b:1"].supports(2));
+        assert!(!versions["a:1"].supports(2));
+        assert_eq!(versions["b:1"].latest(), Some(2));
+
+        store(&mut conn, "a:1", &version("coinswap/0.0.9", &[]), now).unwrap();
+        let versions = load(&mut conn).unwrap();
+        assert_eq!(versions["a:1"].protocols, Vec::<u32>::new());
+        assert_eq!(versions["a:1"].latest(), None);
+
+        rename(&mut conn, "b:1", "c:1").unwrap();
+        delete(&mut conn, "a:1").unwrap();
+        let versions = load(&mut conn).unwrap();
+        assert_eq!(versions.keys().collect::<Vec<_>>(), vec!["c:1"]);
+    }
+}
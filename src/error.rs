@@ -8,10 +8,14 @@ pub enum TrackerError {
     MempoolIndexerError,
     Shutdown,
     ParsingError,
+    InvalidBond(String),
+    InvalidSignature(String),
     SendError,
     IOError(std::io::Error),
     RPCError(bitcoincore_rpc::Error),
     SerdeCbor(serde_cbor::Error),
+    Database(diesel::result::Error),
+    Pool(r2d2::Error),
     General(String),
 }
 
@@ -27,6 +31,18 @@ impl From<bitcoincore_rpc::Error> for TrackerError {
     }
 }
 
+impl From<diesel::result::Error> for TrackerError {
+    fn from(value: diesel::result::Error) -> Self {
+        TrackerError::Database(value)
+    }
+}
+
+impl From<r2d2::Error> for TrackerError {
+    fn from(value: r2d2::Error) -> Self {
+        TrackerError::Pool(value)
+    }
+}
+
 impl Error for TrackerError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         None
@@ -66,10 +82,14 @@ impl TrackerError {
             TrackerError::MempoolIndexerError => "MempoolIndexerError",
             TrackerError::Shutdown => "Shutdown",
             TrackerError::ParsingError => "ParsingError",
+            TrackerError::InvalidBond(_) => "InvalidBond",
+            TrackerError::InvalidSignature(_) => "InvalidSignature",
             TrackerError::SendError => "SendError",
             TrackerError::IOError(_) => "IOError",
             TrackerError::RPCError(_) => "RPCError",
             TrackerError::SerdeCbor(_) => "SerdeCbor",
+            TrackerError::Database(_) => "Database",
+            TrackerError::Pool(_) => "Pool",
             TrackerError::General(_) => "General",
         }
     }
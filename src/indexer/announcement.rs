@@ -0,0 +1,635 @@
+//! Maker announcements embedded in an `OP_RETURN` output.
+//!
+//! A version 1 payload is laid out as:
+//!
+//! ```text
+//! magic "CSAN" (4) | version (1) | network magic (4) | address type (1)
+//! | host length (1) | host | port (2, BE) | bond pubkey (33)
+//! | bond locktime (4, LE) | signature (64)
+//! ```
+//!
+//! The signature is a compact ECDSA signature by the fidelity bond key over
+//! the double SHA256 of every byte before it.
+//!
+//! With a v3 onion host the payload is 176 bytes. That is more than the 83
+//! byte `OP_RETURN` output that Bitcoin Core relays by default before v30, so
+//! announcement transactions need v30 or later, or a `-datacarriersize` of at
+//! least 179, along the relay path to a miner. The signature and bond key
+//! alone take 97 bytes, so no layout of this payload fits the old limit.
+
+use bitcoincore_rpc::bitcoin::{
+    Network, OutPoint, PublicKey, Transaction,
+    absolute::{Height, LockTime},
+    hashes::{Hash, sha256d},
+    p2p::Magic,
+    secp256k1::{Message, Secp256k1, ecdsa::Signature},
+};
+#[cfg(not(feature = "integration-test"))]
+use data_encoding::BASE32_NOPAD;
+#[cfg(not(feature = "integration-test"))]
+use sha3::{Digest, Sha3_256};
+#[cfg(feature = "integration-test")]
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::{indexer::fidelity::fidelity_script_pubkey, types::FidelityBond};
+
+pub(crate) const ANNOUNCEMENT_MAGIC: [u8; 4] = *b"CSAN";
+pub(crate) const ANNOUNCEMENT_VERSION: u8 = 1;
+
+/// Length of a base32 encoded v3 onion service id.
+#[cfg(not(feature = "integration-test"))]
+const ONION_V3_ID_LEN: usize = 56;
+#[cfg(not(feature = "integration-test"))]
+const ONION_V3_VERSION: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum AddressType {
+    OnionV3 = 1,
+    /// Clearnet types are only accepted by `integration-test` builds.
+    Ipv4 = 2,
+    Ipv6 = 3,
+    Dns = 4,
+}
+
+impl TryFrom<u8> for AddressType {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(AddressType::OnionV3),
+            2 => Ok(AddressType::Ipv4),
+            3 => Ok(AddressType::Ipv6),
+            4 => Ok(AddressType::Dns),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A parsed and signature-checked maker announcement.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Announcement {
+    pub version: u8,
+    pub network: Network,
+    pub address_type: AddressType,
+    pub host: String,
+    pub port: u16,
+    pub bond_pubkey: PublicKey,
+    pub bond_lock_time: LockTime,
+    pub signature: Signature,
+}
+
+impl Announcement {
+    pub fn address(&self) -> String {
+        match self.address_type {
+            AddressType::Ipv6 => format!("[{}]:{}", self.host, self.port),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    /// Parses a payload and checks that it targets `network`, carries an
+    /// address we can reach and is signed by the bond key.
+    pub fn from_payload(payload: &[u8], network: Network) -> Option<Self> {
+        let mut reader = Reader(payload);
+        if reader.take(4)? != ANNOUNCEMENT_MAGIC || reader.byte()? != ANNOUNCEMENT_VERSION {
+            return None;
+        }
+        let magic = Magic::from_bytes(reader.take(4)?.try_into().ok()?);
+        if Network::from_magic(magic)? != network {
+            return None;
+        }
+        let address_type = AddressType::try_from(reader.byte()?).ok()?;
+        let host_len = reader.byte()? as usize;
+        let host = String::from_utf8(reader.take(host_len)?.to_vec()).ok()?;
+        let port = u16::from_be_bytes(reader.take(2)?.try_into().ok()?);
+        let bond_pubkey = PublicKey::from_slice(reader.take(33)?).ok()?;
+        let bond_lock_time =
+            LockTime::from_consensus(u32::from_le_bytes(reader.take(4)?.try_into().ok()?));
+        let signed_len = payload.len() - reader.0.len();
+        let signature = Signature::from_compact(reader.take(64)?).ok()?;
+        if !reader.0.is_empty() {
+            return None;
+        }
+
+        let announcement = Announcement {
+            version: ANNOUNCEMENT_VERSION,
+            network,
+            address_type,
+            host,
+            port,
+            bond_pubkey,
+            bond_lock_time,
+            signature,
+        };
+        if !announcement.has_valid_address() {
+            return None;
+        }
+
+        let message = signing_message(&payload[..signed_len]);
+        Secp256k1::verification_only()
+            .verify_ecdsa(
+                &message,
+                &announcement.signature,
+                &announcement.bond_pubkey.inner,
+            )
+            .ok()?;
+
+        Some(announcement)
+    }
+
+    /// Serializes everything the signature commits to.
+    #[cfg(test)]
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(128);
+        bytes.extend(ANNOUNCEMENT_MAGIC);
+        bytes.push(self.version);
+        bytes.extend(self.network.magic().to_bytes());
+        bytes.push(self.address_type as u8);
+        bytes.push(self.host.len() as u8);
+        bytes.extend(self.host.as_bytes());
+        bytes.extend(self.port.to_be_bytes());
+        bytes.extend(self.bond_pubkey.inner.serialize());
+        bytes.extend(self.bond_lock_time.to_consensus_u32().to_le_bytes());
+        bytes
+    }
+
+    #[cfg(test)]
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = self.signed_bytes();
+        bytes.extend(self.signature.serialize_compact());
+        bytes
+    }
+
+    fn has_valid_address(&self) -> bool {
+        #[cfg(not(feature = "integration-test"))]
+        let type_matches = self.address_type == AddressType::OnionV3;
+        #[cfg(feature = "integration-test")]
+        let type_matches = host_address_type(&self.host) == Some(self.address_type);
+        type_matches && is_valid_maker_address(&self.address())
+    }
+}
+
+pub(crate) fn signing_message(signed_bytes: &[u8]) -> Message {
+    Message::from_digest(sha256d::Hash::hash(signed_bytes).to_byte_array())
+}
+
+/// An announcement found in a transaction.
+#[derive(Debug, Clone)]
+pub(crate) enum TxAnnouncement {
+    /// A signed announcement and the bond output it funds in the same
+    /// transaction. The bond's `conf_height` is left for the caller to set.
+    Signed {
+        announcement: Box<Announcement>,
+        bond: FidelityBond,
+    },
+    /// Bare `host:port` string accepted by the pre-versioning heuristic.
+    Legacy(String),
+}
+
+impl TxAnnouncement {
+    pub fn address(&self) -> String {
+        match self {
+            TxAnnouncement::Signed { announcement, .. } => announcement.address(),
+            TxAnnouncement::Legacy(address) => address.clone(),
+        }
+    }
+
+    pub fn bond(&self) -> Option<&FidelityBond> {
+        match self {
+            TxAnnouncement::Signed { bond, .. } => Some(bond),
+            TxAnnouncement::Legacy(_) => None,
+        }
+    }
+}
+
+/// Looks for a maker announcement in `tx`. A signed announcement only counts
+/// if the same transaction funds the fidelity bond it commits to. The legacy
+/// heuristic (any non-zero locktime transaction with 2-5 outputs and an
+/// `OP_RETURN` address) is only consulted when `legacy` is set.
+pub(crate) fn find_announcement(
+    tx: &Transaction,
+    network: Network,
+    legacy: bool,
+) -> Option<TxAnnouncement> {
+    let signed = tx.output.iter().find_map(|txout| {
+        let data = op_return_data(txout.script_pubkey.as_bytes())?;
+        Announcement::from_payload(data, network)
+    });
+    if let Some(announcement) = signed {
+        return bond_output(tx, &announcement).map(|bond| TxAnnouncement::Signed {
+            announcement: Box::new(announcement),
+            bond,
+        });
+    }
+    if legacy {
+        return legacy_announcement(tx).map(TxAnnouncement::Legacy);
+    }
+    None
+}
+
+fn bond_output(tx: &Transaction, announcement: &Announcement) -> Option<FidelityBond> {
+    let script_pubkey =
+        fidelity_script_pubkey(&announcement.bond_lock_time, &announcement.bond_pubkey);
+    let (vout, txout) = tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_, txout)| txout.script_pubkey == script_pubkey)?;
+    Some(FidelityBond {
+        outpoint: OutPoint::new(tx.compute_txid(), vout as u32),
+        amount: txout.value,
+        lock_time: announcement.bond_lock_time,
+        pubkey: announcement.bond_pubkey,
+        conf_height: None,
+        cert_expiry: None,
+    })
+}
+
+fn legacy_announcement(tx: &Transaction) -> Option<String> {
+    if tx.lock_time == LockTime::Blocks(Height::ZERO) {
+        return None;
+    }
+
+    if tx.output.len() < 2 || tx.output.len() > 5 {
+        return None;
+    }
+
+    tx.output.iter().find_map(|txout| {
+        let data = op_return_data(txout.script_pubkey.as_bytes())?;
+        let decoded = String::from_utf8(data.to_vec()).ok()?;
+        is_valid_maker_address(&decoded).then_some(decoded)
+    })
+}
+
+/// Returns the data pushed by an `OP_RETURN` script.
+fn op_return_data(script: &[u8]) -> Option<&[u8]> {
+    if script.is_empty() || script[0] != 0x6a {
+        return None;
+    }
+    if script.len() < 2 {
+        return None;
+    }
+    let (data_start, data_len) = match script[1] {
+        n @ 0x01..=0x4b => (2, n as usize),
+        0x4c => {
+            if script.len() < 3 {
+                return None;
+            }
+            (3, script[2] as usize)
+        }
+        0x4d => {
+            if script.len() < 4 {
+                return None;
+            }
+            let len = u16::from_le_bytes([script[2], script[3]]) as usize;
+            (4, len)
+        }
+        _ => {
+            return None;
+        }
+    };
+    if script.len() < data_start + data_len {
+        return None;
+    }
+
+    Some(&script[data_start..data_start + data_len])
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+}
+
+/// Checks a maker address as the current build dials it: a v3 onion service
+/// over Tor, or a clearnet socket address for integration tests.
+pub(crate) fn is_valid_maker_address(s: &str) -> bool {
+    #[cfg(not(feature = "integration-test"))]
+    return is_valid_onion_address(s);
+    #[cfg(feature = "integration-test")]
+    return is_valid_address(s);
+}
+
+/// Checks `<service id>.onion:<port>` against the v3 onion format from
+/// tor's rend-spec-v3: the service id is the lowercase base32 encoding of
+/// `PUBKEY (32) | CHECKSUM (2) | VERSION (1)`, where `CHECKSUM` is the first
+/// two bytes of `SHA3-256(".onion checksum" | PUBKEY | VERSION)`.
+#[cfg(not(feature = "integration-test"))]
+fn is_valid_onion_address(s: &str) -> bool {
+    let Some((host, port)) = s.split_once(':') else {
+        return false;
+    };
+    let Some(service_id) = host.strip_suffix(".onion") else {
+        return false;
+    };
+    // v2 service ids are 16 characters long and fail here.
+    if service_id.len() != ONION_V3_ID_LEN || service_id.bytes().any(|b| b.is_ascii_uppercase()) {
+        return false;
+    }
+    let Ok(decoded) = BASE32_NOPAD.decode(service_id.to_ascii_uppercase().as_bytes()) else {
+        return false;
+    };
+    let (pubkey, rest) = decoded.split_at(32);
+    let (checksum, version) = rest.split_at(2);
+    if version != [ONION_V3_VERSION] {
+        return false;
+    }
+    let digest = Sha3_256::new()
+        .chain_update(b".onion checksum")
+        .chain_update(pubkey)
+        .chain_update(version)
+        .finalize();
+    if checksum != &digest[..2] {
+        return false;
+    }
+    matches!(port.parse::<u16>(), Ok(p) if p > 0)
+}
+
+/// Checks `<host>:<port>` where the host is an IPv4 literal, a bracketed IPv6
+/// literal or a DNS hostname.
+#[cfg(feature = "integration-test")]
+fn is_valid_address(s: &str) -> bool {
+    let Some((host, port)) = s.rsplit_once(':') else {
+        return false;
+    };
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => match bracketed.strip_suffix(']') {
+            Some(ip) if Ipv6Addr::from_str(ip).is_ok() => ip,
+            _ => return false,
+        },
+        None if host.contains(':') => return false,
+        None => host,
+    };
+    if host_address_type(host).is_none() {
+        return false;
+    }
+    matches!(port.parse::<u16>(), Ok(p) if p > 0)
+}
+
+/// Classifies a clearnet host. Onion names are not hostnames we can resolve.
+#[cfg(feature = "integration-test")]
+fn host_address_type(host: &str) -> Option<AddressType> {
+    if Ipv4Addr::from_str(host).is_ok() {
+        return Some(AddressType::Ipv4);
+    }
+    if Ipv6Addr::from_str(host).is_ok() {
+        return Some(AddressType::Ipv6);
+    }
+    (is_valid_hostname(host) && !host.ends_with(".onion")).then_some(AddressType::Dns)
+}
+
+/// RFC 1123 hostname. The last label must not be numeric so that malformed
+/// IPv4 literals such as `256.0.0.1` are not taken for names.
+#[cfg(feature = "integration-test")]
+fn is_valid_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.len() > 253 {
+        return false;
+    }
+    let valid_labels = host.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+    let numeric_tld = host
+        .rsplit('.')
+        .next()
+        .is_some_and(|tld| tld.bytes().all(|b| b.is_ascii_digit()));
+    valid_labels && !numeric_tld
+}
+
+#[cfg(not(feature = "integration-test"))]
+#[cfg(test)]
+mod tests_onion {
+    use super::*;
+
+    #[test]
+    fn test_valid_onion_address() {
+        assert!(is_valid_onion_address(
+            "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:1234"
+        ));
+        assert!(is_valid_onion_address(
+            "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion:65535"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_onion_address() {
+        let valid = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+        assert!(!is_valid_onion_address("example.com:1234"));
+        assert!(!is_valid_onion_address(&format!("{valid}:0")));
+        assert!(!is_valid_onion_address(valid));
+        assert!(!is_valid_onion_address("127.0.0.1:8080"));
+        assert!(!is_valid_onion_address("x.onion:1"));
+        // v2 address.
+        assert!(!is_valid_onion_address("expyuzz4wqqyqhjn.onion:80"));
+        // Bad checksum.
+        assert!(!is_valid_onion_address(
+            "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wia.onion:80"
+        ));
+        // Uppercase is not the canonical form.
+        assert!(!is_valid_onion_address(&format!(
+            "{}:80",
+            valid.to_ascii_uppercase().replace(".ONION", ".onion")
+        )));
+    }
+}
+
+#[cfg(feature = "integration-test")]
+#[cfg(test)]
+mod tests_clearnet {
+    use super::*;
+
+    #[test]
+    fn test_valid_clearnet_address() {
+        assert!(is_valid_address("127.0.0.1:8080"));
+        assert!(is_valid_address("192.168.1.1:65535"));
+        assert!(is_valid_address("[::1]:8080"));
+        assert!(is_valid_address("[2001:db8::7]:6102"));
+        assert!(is_valid_address("localhost:6102"));
+        assert!(is_valid_address("maker-1.test.example:6102"));
+    }
+
+    #[test]
+    fn test_invalid_clearnet_address() {
+        assert!(!is_valid_address("example.onion:1234"));
+        assert!(!is_valid_address("256.0.0.1:8080"));
+        assert!(!is_valid_address("127.0.0.1:0"));
+        assert!(!is_valid_address("127.0.0.1"));
+        assert!(!is_valid_address("::1:8080"));
+        assert!(!is_valid_address("[127.0.0.1]:8080"));
+        assert!(!is_valid_address("-maker.example:8080"));
+        assert!(!is_valid_address("maker_1.example:8080"));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::{
+        Amount, ScriptBuf, TxOut,
+        opcodes::all::OP_RETURN,
+        script::{Builder, PushBytesBuf},
+        secp256k1::SecretKey,
+    };
+
+    #[cfg(not(feature = "integration-test"))]
+    pub(crate) const TEST_HOST: (AddressType, &str) = (
+        AddressType::OnionV3,
+        "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion",
+    );
+    #[cfg(feature = "integration-test")]
+    pub(crate) const TEST_HOST: (AddressType, &str) = (AddressType::Ipv4, "127.0.0.1");
+
+    pub(crate) fn bond_key() -> SecretKey {
+        SecretKey::from_slice(&[1; 32]).unwrap()
+    }
+
+    pub(crate) fn signed_announcement(
+        network: Network,
+        port: u16,
+        key: &SecretKey,
+        bond_lock_time: LockTime,
+    ) -> Announcement {
+        let secp = Secp256k1::new();
+        let mut announcement = Announcement {
+            version: ANNOUNCEMENT_VERSION,
+            network,
+            address_type: TEST_HOST.0,
+            host: TEST_HOST.1.to_string(),
+            port,
+            bond_pubkey: PublicKey::new(key.public_key(&secp)),
+            bond_lock_time,
+            signature: Signature::from_compact(&[1; 64]).unwrap(),
+        };
+        let message = signing_message(&announcement.signed_bytes());
+        announcement.signature = secp.sign_ecdsa(&message, key);
+        announcement
+    }
+
+    /// Output funding the bond `announcement` commits to.
+    pub(crate) fn bond_txout(announcement: &Announcement, amount: Amount) -> TxOut {
+        TxOut {
+            value: amount,
+            script_pubkey: fidelity_script_pubkey(
+                &announcement.bond_lock_time,
+                &announcement.bond_pubkey,
+            ),
+        }
+    }
+
+    pub(crate) fn announcement_script(payload: Vec<u8>) -> ScriptBuf {
+        Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(PushBytesBuf::try_from(payload).unwrap())
+            .into_script()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::*;
+    use super::*;
+    use bitcoincore_rpc::bitcoin::{Amount, TxOut, transaction::Version};
+
+    fn tx_with(script_payload: Vec<u8>, lock_time: LockTime) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: Default::default(),
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: announcement_script(script_payload),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_signed_announcement_roundtrip() {
+        let lock_time = LockTime::from_height(900_000).unwrap();
+        let announcement = signed_announcement(Network::Regtest, 6102, &bond_key(), lock_time);
+        let parsed = Announcement::from_payload(&announcement.to_payload(), Network::Regtest);
+        assert_eq!(parsed, Some(announcement.clone()));
+        assert_eq!(parsed.unwrap().address(), format!("{}:6102", TEST_HOST.1));
+
+        // Wrong network, trailing bytes and a tampered port are all rejected.
+        assert!(Announcement::from_payload(&announcement.to_payload(), Network::Bitcoin).is_none());
+        let mut trailing = announcement.to_payload();
+        trailing.push(0);
+        assert!(Announcement::from_payload(&trailing, Network::Regtest).is_none());
+        let mut tampered = announcement.clone();
+        tampered.port = 6103;
+        assert!(Announcement::from_payload(&tampered.to_payload(), Network::Regtest).is_none());
+    }
+
+    #[test]
+    fn test_legacy_announcement_requires_flag() {
+        let address = format!("{}:6102", TEST_HOST.1);
+        let tx = tx_with(
+            address.as_bytes().to_vec(),
+            LockTime::from_height(100).unwrap(),
+        );
+        assert!(find_announcement(&tx, Network::Regtest, false).is_none());
+        let found = find_announcement(&tx, Network::Regtest, true).unwrap();
+        assert_eq!(found.address(), address);
+
+        let lock_time = LockTime::from_height(900_000).unwrap();
+        let signed = signed_announcement(Network::Regtest, 6102, &bond_key(), lock_time);
+        let mut tx = tx_with(signed.to_payload(), LockTime::ZERO);
+        tx.output[0] = bond_txout(&signed, Amount::from_sat(10_000));
+        assert!(matches!(
+            find_announcement(&tx, Network::Regtest, false),
+            Some(TxAnnouncement::Signed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_signed_announcement_requires_bond_output() {
+        let lock_time = LockTime::from_height(900_000).unwrap();
+        let signed = signed_announcement(Network::Regtest, 6102, &bond_key(), lock_time);
+        let mut tx = tx_with(signed.to_payload(), LockTime::ZERO);
+        assert!(find_announcement(&tx, Network::Regtest, true).is_none());
+
+        // A bond to a different lock time does not count either.
+        let other = Announcement {
+            bond_lock_time: LockTime::from_height(900_001).unwrap(),
+            ..signed.clone()
+        };
+        tx.output[0] = bond_txout(&other, Amount::from_sat(10_000));
+        assert!(find_announcement(&tx, Network::Regtest, false).is_none());
+
+        tx.output[0] = bond_txout(&signed, Amount::from_sat(10_000));
+        let bond = find_announcement(&tx, Network::Regtest, false)
+            .unwrap()
+            .bond()
+            .cloned()
+            .unwrap();
+        assert_eq!(bond.outpoint, OutPoint::new(tx.compute_txid(), 0));
+        assert_eq!(bond.amount, Amount::from_sat(10_000));
+        assert_eq!(bond.pubkey, signed.bond_pubkey);
+    }
+}
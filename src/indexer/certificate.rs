@@ -0,0 +1,114 @@
+//! Fidelity bond certificates as created by coinswap makers.
+//!
+//! A maker binds its bond to its address by signing, with the bond key, the
+//! Bitcoin signed-message hash of
+//!
+//! ```text
+//! fidelity-bond-cert|<outpoint>|<pubkey>|<cert expiry>|<locktime>|<amount>|<address>
+//! ```
+//!
+//! The certificate expires at height `cert_expiry * 2016`.
+//!
+//! Makers send that 32-byte hash as `cert_hash`. Makers from before
+//! certificates were checked send a 20-byte `hash160` instead, see
+//! `types::CertHash`, and are refused until they upgrade.
+
+use bitcoincore_rpc::bitcoin::{
+    hashes::{Hash, sha256d},
+    secp256k1::{Message, Secp256k1},
+    sign_message::signed_msg_hash,
+};
+
+use crate::{
+    error::TrackerError,
+    types::{Certificate, DIFFICULTY_PERIOD, FidelityBond},
+};
+
+pub(crate) fn cert_hash(bond: &FidelityBond, cert_expiry: u32, address: &str) -> sha256d::Hash {
+    let message = format!(
+        "fidelity-bond-cert|{}|{}|{}|{}|{}|{}",
+        bond.outpoint, bond.pubkey, cert_expiry, bond.lock_time, bond.amount, address
+    );
+    signed_msg_hash(&message)
+}
+
+/// Checks that `certificate` binds `bond` to `address`, is signed by the bond
+/// key and has not expired at `tip_height`.
+pub(crate) fn verify_certificate(
+    bond: &FidelityBond,
+    certificate: &Certificate,
+    address: &str,
+    tip_height: u64,
+) -> Result<(), TrackerError> {
+    let cert_expiry = bond.cert_expiry.ok_or_else(|| {
+        TrackerError::InvalidBond(format!("{} has no certificate expiry", bond.outpoint))
+    })?;
+    if tip_height >= cert_expiry as u64 * DIFFICULTY_PERIOD {
+        return Err(TrackerError::InvalidBond(format!(
+            "certificate for {} expired at period {cert_expiry}",
+            bond.outpoint
+        )));
+    }
+    if certificate.hash != cert_hash(bond, cert_expiry, address) {
+        return Err(TrackerError::InvalidSignature(format!(
+            "certificate for {} does not match {address}",
+            bond.outpoint
+        )));
+    }
+    let message = Message::from_digest(certificate.hash.to_byte_array());
+    Secp256k1::verification_only()
+        .verify_ecdsa(&message, &certificate.sig, &bond.pubkey.inner)
+        .map_err(|e| TrackerError::InvalidSignature(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{bond, certify, key},
+        types::{CertHash, FidelityProof},
+    };
+    use bitcoincore_rpc::bitcoin::hashes::hash160;
+
+    const ADDRESS: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:6102";
+
+    #[test]
+    fn test_verify_certificate() {
+        let (bond, cert) = certify(bond(7), &key(1), ADDRESS);
+        let tip = bond.cert_expiry.unwrap() as u64 * DIFFICULTY_PERIOD - 1;
+        assert!(verify_certificate(&bond, &cert, ADDRESS, tip).is_ok());
+
+        // Expired, bound to another address, or signed by another key.
+        assert!(verify_certificate(&bond, &cert, ADDRESS, tip + 1).is_err());
+        let other_address = ADDRESS.replace("6102", "6103");
+        assert!(verify_certificate(&bond, &cert, &other_address, tip).is_err());
+        let (_, forged) = certify(bond.clone(), &key(2), ADDRESS);
+        assert!(verify_certificate(&bond, &forged, ADDRESS, tip).is_err());
+    }
+
+    #[test]
+    fn test_proof_decodes_both_hash_formats() {
+        let (bond, cert) = certify(bond(7), &key(1), ADDRESS);
+        let proof = |cert_hash| FidelityProof {
+            bond: bond.clone(),
+            cert_hash,
+            cert_sig: cert.sig,
+        };
+        let cbor = |proof: &FidelityProof| {
+            serde_cbor::from_slice::<FidelityProof>(&serde_cbor::to_vec(proof).unwrap()).unwrap()
+        };
+        let json = |proof: &FidelityProof| {
+            serde_json::from_str::<FidelityProof>(&serde_json::to_string(proof).unwrap()).unwrap()
+        };
+
+        let current = proof(CertHash::SignedMessage(cert.hash));
+        assert_eq!(cbor(&current).certificate().unwrap(), cert);
+        assert_eq!(json(&current), current);
+
+        // Older makers' proofs still parse, but cannot be verified.
+        let legacy = proof(CertHash::Legacy(hash160::Hash::hash(b"cert")));
+        assert_eq!(cbor(&legacy), legacy);
+        assert_eq!(json(&legacy), legacy);
+        assert!(legacy.certificate().is_err());
+    }
+}
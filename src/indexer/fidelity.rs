@@ -0,0 +1,210 @@
+use bitcoincore_rpc::bitcoin::{
+    PublicKey, ScriptBuf,
+    absolute::LockTime,
+    opcodes::all::{OP_CHECKSIGVERIFY, OP_CLTV},
+    script::Builder,
+};
+use diesel::SqliteConnection;
+
+use crate::{
+    error::TrackerError,
+    indexer::{certificate::verify_certificate, rpc::BitcoinRpc, utxo_indexer::lookup_utxo},
+    types::{FidelityBond, FidelityProof},
+};
+
+/// Redeem script of a coinswap fidelity bond: the bond key must sign and the
+/// output cannot be spent before `lock_time`.
+pub(crate) fn fidelity_redeemscript(lock_time: &LockTime, pubkey: &PublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_key(pubkey)
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_lock_time(*lock_time)
+        .push_opcode(OP_CLTV)
+        .into_script()
+}
+
+pub(crate) fn fidelity_script_pubkey(lock_time: &LockTime, pubkey: &PublicKey) -> ScriptBuf {
+    ScriptBuf::new_p2wsh(&fidelity_redeemscript(lock_time, pubkey).wscript_hash())
+}
+
+/// Checks that `bond` is an unspent, confirmed output paying the expected
+/// amount to the fidelity script. Outputs that were never indexed (below the
+/// start height, or skipped in light mode) are looked up through bitcoind.
+///
+/// Returns the bond with its confirmation height filled in.
+pub(crate) fn verify_bond(
+    conn: &mut SqliteConnection,
+    rpc: &BitcoinRpc,
+    bond: &FidelityBond,
+) -> Result<FidelityBond, TrackerError> {
+    let utxo = lookup_utxo(conn, rpc, &bond.outpoint)?
+        .filter(|utxo| !utxo.spent)
+        .ok_or_else(|| {
+            TrackerError::InvalidBond(format!("{} is spent or unknown", bond.outpoint))
+        })?;
+
+    let tx_out = utxo.tx_out();
+    if tx_out.value != bond.amount {
+        return Err(TrackerError::InvalidBond(format!(
+            "{} holds {} but {} was claimed",
+            bond.outpoint, tx_out.value, bond.amount
+        )));
+    }
+    if tx_out.script_pubkey != fidelity_script_pubkey(&bond.lock_time, &bond.pubkey) {
+        return Err(TrackerError::InvalidBond(format!(
+            "{} does not pay to the fidelity script",
+            bond.outpoint
+        )));
+    }
+
+    let conf_height = utxo
+        .block_height
+        .ok_or_else(|| TrackerError::InvalidBond(format!("{} is unconfirmed", bond.outpoint)))?;
+
+    Ok(FidelityBond {
+        conf_height: Some(conf_height as u32),
+        ..bond.clone()
+    })
+}
+
+/// Runs every check on a maker's `Post`: the bond must be valid, have at
+/// least `min_confirmations` and come with a live certificate for `address`.
+///
+/// Returns the bond with its confirmation height filled in.
+pub(crate) fn verify_proof(
+    conn: &mut SqliteConnection,
+    rpc: &BitcoinRpc,
+    proof: &FidelityProof,
+    address: &str,
+    min_confirmations: u32,
+) -> Result<FidelityBond, TrackerError> {
+    let tip_height = rpc.get_blockchain_info()?.blocks;
+    let bond = verify_bond(conn, rpc, &proof.bond)?;
+    verify_depth(&bond, tip_height, min_confirmations)?;
+    verify_certificate(&bond, &proof.certificate()?, address, tip_height)?;
+    Ok(bond)
+}
+
+/// Checks that `bond` has at least `min_confirmations` at `tip_height`.
+pub(crate) fn verify_depth(
+    bond: &FidelityBond,
+    tip_height: u64,
+    min_confirmations: u32,
+) -> Result<(), TrackerError> {
+    let conf_height = bond
+        .conf_height
+        .ok_or_else(|| TrackerError::InvalidBond(format!("{} is unconfirmed", bond.outpoint)))?
+        as u64;
+    let confirmations = (tip_height + 1).saturating_sub(conf_height);
+    if confirmations < min_confirmations as u64 {
+        return Err(TrackerError::InvalidBond(format!(
+            "{} has {confirmations} confirmations, {min_confirmations} required",
+            bond.outpoint
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        model::{Bond, MempoolInput, MempoolTx, Utxo},
+        pruner::{RetentionPolicy, prune},
+        schema::{fidelity_bonds, mempool_inputs, mempool_tx, utxos},
+    };
+    use crate::test_utils::{self, memory_db};
+    use bitcoincore_rpc::bitcoin::{Amount, TxOut};
+    use diesel::{ExpressionMethods, RunQueryDsl};
+
+    /// Bond as claimed in a `Post`, before the tracker found its height.
+    fn bond() -> FidelityBond {
+        FidelityBond {
+            conf_height: None,
+            ..test_utils::bond(7)
+        }
+    }
+
+    fn conn_with(bond: &FidelityBond, value: Amount) -> SqliteConnection {
+        let mut conn = memory_db();
+        let tx_out = TxOut {
+            value,
+            script_pubkey: fidelity_script_pubkey(&bond.lock_time, &bond.pubkey),
+        };
+        diesel::insert_into(utxos::table)
+            .values(&Utxo::new(&bond.outpoint, &tx_out, Some(850_123)))
+            .execute(&mut conn)
+            .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_verify_indexed_bond() {
+        let rpc = BitcoinRpc::new("http://127.0.0.1:1".into(), "u".into(), "p".into()).unwrap();
+        let bond = bond();
+
+        let mut conn = conn_with(&bond, bond.amount);
+        let verified = verify_bond(&mut conn, &rpc, &bond).unwrap();
+        assert_eq!(verified.conf_height, Some(850_123));
+
+        let mut conn = conn_with(&bond, Amount::from_sat(1_000));
+        assert!(matches!(
+            verify_bond(&mut conn, &rpc, &bond),
+            Err(TrackerError::InvalidBond(_))
+        ));
+    }
+
+    #[test]
+    fn test_unconfirmed_bond_fails_depth() {
+        assert!(verify_depth(&bond(), 850_200, 0).is_err());
+        let confirmed = FidelityBond {
+            conf_height: Some(850_123),
+            ..bond()
+        };
+        assert!(verify_depth(&confirmed, 850_127, 6).is_err());
+        assert!(verify_depth(&confirmed, 850_128, 6).is_ok());
+    }
+
+    #[test]
+    fn test_bond_verifies_after_its_mempool_spend_expired() {
+        let rpc = BitcoinRpc::new("http://127.0.0.1:1".into(), "u".into(), "p".into()).unwrap();
+        let bond = bond();
+        let mut conn = conn_with(&bond, bond.amount);
+        diesel::insert_into(fidelity_bonds::table)
+            .values(&Bond::new("maker.onion:6102", &bond, None))
+            .execute(&mut conn)
+            .unwrap();
+
+        // Spent by a mempool transaction that was dropped a month ago.
+        let spender = "ab".repeat(32);
+        diesel::update(utxos::table)
+            .set((
+                utxos::spent.eq(true),
+                utxos::spent_by_txid.eq(hex::decode(&spender).unwrap()),
+                utxos::confirmed.eq(false),
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(mempool_tx::table)
+            .values(&MempoolTx {
+                txid: spender.clone(),
+                seen_at: (chrono::Utc::now() - chrono::TimeDelta::days(30)).naive_utc(),
+            })
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(mempool_inputs::table)
+            .values(&MempoolInput {
+                txid: spender,
+                input_txid: bond.outpoint.txid.to_string(),
+                input_vout: bond.outpoint.vout as i32,
+            })
+            .execute(&mut conn)
+            .unwrap();
+        assert!(verify_bond(&mut conn, &rpc, &bond).is_err());
+
+        let report = prune(&mut conn, &RetentionPolicy::default()).unwrap();
+        assert_eq!(report.released_bonds, vec![bond.outpoint]);
+        let verified = verify_bond(&mut conn, &rpc, &bond).unwrap();
+        assert_eq!(verified.conf_height, Some(850_123));
+    }
+}
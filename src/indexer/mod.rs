@@ -1,4 +1,40 @@
+use std::str::FromStr;
+
+use crate::error::TrackerError;
+
+mod announcement;
+mod certificate;
+mod fidelity;
 mod tracker_indexer;
 pub use tracker_indexer::run;
-mod rpc;
+pub(crate) mod rpc;
 mod utxo_indexer;
+
+pub(crate) use announcement::is_valid_maker_address;
+#[cfg(test)]
+pub(crate) use certificate::cert_hash;
+pub(crate) use certificate::verify_certificate;
+pub(crate) use fidelity::{verify_depth, verify_proof};
+
+/// Which outputs the indexer stores in `utxos`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexMode {
+    /// Every output of every block and mempool transaction.
+    #[default]
+    Full,
+    /// Only fidelity bond candidates, announcement outputs and watched
+    /// outpoints. Everything else is looked up through `gettxout`.
+    Light,
+}
+
+impl FromStr for IndexMode {
+    type Err = TrackerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(IndexMode::Full),
+            "light" => Ok(IndexMode::Light),
+            _ => Err(TrackerError::General(format!("Unknown index mode: {s}"))),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use bitcoincore_rpc::{
     Auth, Client, RpcApi,
-    bitcoin::{Block, BlockHash, Transaction, Txid},
-    json::GetBlockchainInfoResult,
+    bitcoin::{Block, BlockHash, OutPoint, Transaction, Txid},
+    json::{GetBlockchainInfoResult, GetTxOutResult},
 };
 
 use crate::error::TrackerError;
@@ -27,6 +27,18 @@ impl BitcoinRpc {
         Ok(tx)
     }
 
+    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Result<Option<GetTxOutResult>, TrackerError> {
+        let tx_out = self
+            .client
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?;
+        Ok(tx_out)
+    }
+
+    pub fn get_block_height(&self, hash: &BlockHash) -> Result<u64, TrackerError> {
+        let header = self.client.get_block_header_info(hash)?;
+        Ok(header.height as u64)
+    }
+
     pub fn get_blockchain_info(&self) -> Result<GetBlockchainInfoResult, TrackerError> {
         let blockchain_info = self.client.get_blockchain_info()?;
         Ok(blockchain_info)
@@ -1,19 +1,23 @@
 use std::{sync::Arc, time::Duration};
 
+use chrono::Utc;
 use diesel::{SqliteConnection, r2d2::ConnectionManager};
 use r2d2::Pool;
-use tokio::{sync::mpsc::Sender, time::Instant};
+use tokio::{
+    sync::mpsc::{self, Sender},
+    time::Instant,
+};
 
-use bitcoincore_rpc::bitcoin::absolute::{Height, LockTime};
-use std::str::FromStr;
-use tracing::info;
+use bitcoincore_rpc::bitcoin::Network;
+use tracing::{error, info};
 
 use super::rpc::BitcoinRpc;
 use crate::{
+    error::TrackerError,
     handle_result,
-    indexer::utxo_indexer::Indexer,
+    indexer::{IndexMode, utxo_indexer::Indexer},
     status,
-    types::{DbRequest, ServerInfo},
+    types::{DbRequest, MakerState, ServerInfo},
 };
 
 pub async fn run(
@@ -21,166 +25,154 @@ pub async fn run(
     db_tx: Sender<DbRequest>,
     status_tx: status::Sender,
     client: BitcoinRpc,
+    mode: IndexMode,
+    start_height: Option<u64>,
+    legacy_announcements: bool,
 ) {
-    info!("Indexer started");
-    let mut utxo_indexer = Indexer::new(pool, &client);
-    let mut last_tip = 0;
+    info!("Indexer started in {:?} mode", mode);
+    let network = match client.get_blockchain_info() {
+        Ok(info) => info.chain,
+        Err(e) => {
+            error!("Failed to query the chain: {e:?}");
+            status::handle_error(&status_tx, e).await;
+            return;
+        }
+    };
+    let mut utxo_indexer = Indexer::new(pool, &client, mode, network, legacy_announcements);
+    match utxo_indexer.recheck_amounts() {
+        Ok(0) => {}
+        Ok(rechecked) => info!("Re-fetched {rechecked} amounts stored before 64-bit amounts"),
+        Err(e) => {
+            error!("Failed to recheck stored amounts: {e:?}");
+            status::handle_error(&status_tx, e).await;
+            return;
+        }
+    }
+    let mut last_tip = match utxo_indexer.next_height() {
+        Ok(height) => height,
+        Err(e) => {
+            error!("Failed to load indexer height: {e:?}");
+            status::handle_error(&status_tx, e).await;
+            return;
+        }
+    };
+    info!("Resuming indexing at height {}", last_tip);
+    // Tip the bonds were last checked against, so they are checked once per
+    // new tip.
+    let mut checked_tip = None;
     loop {
         let blockchain_info = handle_result!(status_tx, client.get_blockchain_info());
         let tip_height = blockchain_info.blocks + 1;
-        utxo_indexer.process_mempool();
-
-        for height in last_tip..tip_height {
-            utxo_indexer.process_block(height);
-            let block_hash = handle_result!(status_tx, client.get_block_hash(height));
-            let block = handle_result!(status_tx, client.get_block(block_hash));
-            for tx in block.txdata {
-                if tx.lock_time == LockTime::Blocks(Height::ZERO) {
-                    continue;
-                }
-
-                if tx.output.len() < 2 || tx.output.len() > 5 {
-                    continue;
-                }
-
-                let onion_address = tx.output.iter().find_map(|txout| {
-                    extract_onion_address_from_script(txout.script_pubkey.as_bytes())
-                });
-
-                if let Some(onion_address) = onion_address {
-                    let server_info = ServerInfo {
-                        onion_address: onion_address.clone(),
-                        cooldown: Instant::now(),
-                        stale: false,
-                    };
-                    info!("New address found: {:?}", onion_address);
-                    let db_request = DbRequest::Add(onion_address, server_info);
+        let spent_bonds = handle_result!(status_tx, utxo_indexer.process_mempool());
+        if !spent_bonds.is_empty() {
+            info!("Bonds spent in the mempool: {:?}", spent_bonds);
+            handle_result!(
+                status_tx,
+                db_tx.send(DbRequest::RevokeBonds(spent_bonds)).await
+            );
+        }
 
-                    handle_result!(status_tx, db_tx.send(db_request).await);
-                }
-            }
+        if checked_tip != Some(blockchain_info.blocks) {
+            let db_request = DbRequest::ExpireBonds {
+                height: blockchain_info.blocks,
+                median_time: blockchain_info.median_time,
+            };
+            handle_result!(status_tx, db_tx.send(db_request).await);
+            checked_tip = Some(blockchain_info.blocks);
         }
-        last_tip = tip_height;
-        tokio::time::sleep(Duration::from_secs(10)).await;
-    }
-}
 
-fn extract_onion_address_from_script(script: &[u8]) -> Option<String> {
-    if script.is_empty() || script[0] != 0x6a {
-        return None;
-    }
-    if script.len() < 2 {
-        return None;
-    }
-    let (data_start, data_len) = match script[1] {
-        n @ 0x01..=0x4b => (2, n as usize),
-        0x4c => {
-            if script.len() < 3 {
-                return None;
+        let birthday = start_height
+            .unwrap_or_else(|| default_start_height(blockchain_info.chain, legacy_announcements));
+        for height in last_tip.max(birthday)..tip_height {
+            let block_hash = handle_result!(status_tx, client.get_block_hash(height));
+            let block = handle_result!(status_tx, client.get_block(block_hash));
+            let update = handle_result!(status_tx, utxo_indexer.scan_block(height, &block));
+
+            for announcement in update.announcements {
+                let onion_address = announcement.address();
+                let server_info = ServerInfo {
+                    onion_address: onion_address.clone(),
+                    cooldown: Instant::now(),
+                    failures: 0,
+                    up_since: None,
+                    first_seen: Utc::now(),
+                    last_seen: None,
+                    bond: announcement.bond().cloned(),
+                    certificate: None,
+                    state: MakerState::Pending,
+                };
+                info!("New address found: {:?}", onion_address);
+                let db_request = DbRequest::Add(onion_address, server_info);
+
+                handle_result!(status_tx, db_tx.send(db_request).await);
             }
-            (3, script[2] as usize)
-        }
-        0x4d => {
-            if script.len() < 4 {
-                return None;
+            if !update.spent_bonds.is_empty() {
+                info!("Bonds spent at height {}: {:?}", height, update.spent_bonds);
+                let db_request = DbRequest::RevokeBonds(update.spent_bonds);
+                handle_result!(status_tx, db_tx.send(db_request).await);
             }
-            let len = u16::from_le_bytes([script[2], script[3]]) as usize;
-            (4, len)
-        }
-        _ => {
-            return None;
-        }
-    };
-    if script.len() < data_start + data_len {
-        return None;
-    }
-
-    let data = &script[data_start..data_start + data_len];
-    let decoded = String::from_utf8(data.to_vec()).ok()?;
-
-    #[cfg(not(feature = "integration-test"))]
-    if is_valid_onion_address(&decoded) {
-        Some(decoded)
-    } else {
-        None
-    }
-
-    #[cfg(feature = "integration-test")]
-    if is_valid_address(&decoded) {
-        Some(decoded)
-    } else {
-        None
-    }
-}
-
-#[cfg(not(feature = "integration-test"))]
-fn is_valid_onion_address(s: &str) -> bool {
-    let parts: Vec<&str> = s.split(':').collect();
-    if parts.len() != 2 {
-        return false;
-    }
-    let domain = parts[0];
-    let port = parts[1];
-    if !domain.ends_with(".onion") {
-        return false;
-    }
-    matches!(port.parse::<u16>(), Ok(p) if p > 0)
-}
-
-#[cfg(feature = "integration-test")]
-fn is_valid_address(s: &str) -> bool {
-    let parts: Vec<&str> = s.split(':').collect();
-    if parts.len() != 2 {
-        return false;
-    }
 
-    let ip = parts[0];
-    let port = parts[1];
-
-    if std::net::Ipv4Addr::from_str(ip).is_err() {
-        return false;
+            // The block is only applied, and the height advanced, once the DB
+            // manager handled its updates. Stopping before that scans the block
+            // again on the next start, and the updates are sent again.
+            let (resp_tx, mut resp_rx) = mpsc::channel(1);
+            handle_result!(status_tx, db_tx.send(DbRequest::Sync(resp_tx)).await);
+            handle_result!(
+                status_tx,
+                resp_rx.recv().await.ok_or(TrackerError::DbManagerExited)
+            );
+            handle_result!(status_tx, utxo_indexer.process_block(height, &block));
+            last_tip = height + 1;
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
     }
-
-    matches!(port.parse::<u16>(), Ok(p) if p > 0)
 }
 
-#[cfg(not(feature = "integration-test"))]
-#[cfg(test)]
-mod tests_onion {
-    use super::*;
-
-    #[test]
-    fn test_valid_onion_address() {
-        assert!(is_valid_onion_address("example.onion:1234"));
-        assert!(is_valid_onion_address("abc1234567890def.onion:65535"));
+/// Mainnet block 850,000, mined in mid-2024. The signed announcement format
+/// in `indexer::announcement` was defined after it, so no signed announcement
+/// can sit in an earlier block. Legacy announcements predate it.
+const MAINNET_BIRTHDAY: u64 = 850_000;
+
+/// Testnet4 started in 2024 and the default signet is small, so both are
+/// scanned from genesis.
+const TESTNET4_BIRTHDAY: u64 = 0;
+const SIGNET_BIRTHDAY: u64 = 0;
+
+/// Testnet3 is scanned from genesis too, for lack of a height known to
+/// predate every announcement there. It is long, so operators still on it
+/// should pass `--start-height`.
+const TESTNET_BIRTHDAY: u64 = 0;
+
+/// Height below which no coinswap fidelity bond or signed announcement can
+/// exist, so the indexer does not need to scan those blocks. Bonds confirmed
+/// earlier are verified through bitcoind when their maker registers. Legacy
+/// announcements have no known first height, so with `legacy` set the chain
+/// is scanned from genesis unless `--start-height` says otherwise.
+fn default_start_height(network: Network, legacy: bool) -> u64 {
+    if legacy {
+        return 0;
     }
-
-    #[test]
-    fn test_invalid_onion_address() {
-        assert!(!is_valid_onion_address("example.com:1234"));
-        assert!(!is_valid_onion_address("example.onion:0"));
-        assert!(!is_valid_onion_address("example.onion"));
-        assert!(!is_valid_onion_address("127.0.0.1:8080"));
+    match network {
+        Network::Bitcoin => MAINNET_BIRTHDAY,
+        Network::Testnet4 => TESTNET4_BIRTHDAY,
+        Network::Signet => SIGNET_BIRTHDAY,
+        Network::Testnet => TESTNET_BIRTHDAY,
+        // Regtest chains are created by the operator, and networks added
+        // to `bitcoin` later have no known birthday.
+        _ => 0,
     }
 }
 
-#[cfg(feature = "integration-test")]
 #[cfg(test)]
-mod tests_ipv4 {
+mod tests {
     use super::*;
 
     #[test]
-    fn test_valid_ipv4_address() {
-        assert!(is_valid_address("127.0.0.1:8080"));
-        assert!(is_valid_address("192.168.1.1:65535"));
-    }
-
-    #[test]
-    fn test_invalid_ipv4_address() {
-        assert!(!is_valid_address("example.onion:1234"));
-        assert!(!is_valid_address("256.0.0.1:8080"));
-        assert!(!is_valid_address("127.0.0.1:0"));
-        assert!(!is_valid_address("127.0.0.1"));
-        assert!(!is_valid_address("::1:8080"));
+    fn test_legacy_announcements_scan_from_genesis() {
+        assert_eq!(
+            default_start_height(Network::Bitcoin, false),
+            MAINNET_BIRTHDAY
+        );
+        assert_eq!(default_start_height(Network::Bitcoin, true), 0);
     }
 }
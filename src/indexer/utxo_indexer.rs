@@ -1,136 +1,714 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
-use crate::db::model::{MempoolInput, MempoolTx, Utxo};
-use crate::db::schema::{mempool_inputs, mempool_tx, utxos};
+use crate::db::model::{
+    IndexerState, MempoolInput, MempoolTx, Utxo, txid_from_bytes, txid_to_bytes,
+};
+use crate::db::schema::{
+    fidelity_bonds, indexer_state, mempool_inputs, mempool_tx, utxo_rechecks, utxos,
+    watched_outpoints,
+};
+use crate::error::TrackerError;
+use crate::indexer::IndexMode;
+use crate::indexer::announcement::{TxAnnouncement, find_announcement};
 use crate::indexer::rpc::BitcoinRpc;
-use chrono::NaiveDateTime;
+use bitcoincore_rpc::bitcoin::{Block, Network, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use chrono::Utc;
 use diesel::SqliteConnection;
+use diesel::dsl::exists;
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
 use r2d2::Pool;
 
+/// Rows per multi-row statement. Keeps every statement well below SQLite's
+/// bound parameter limit.
+const INSERT_CHUNK_SIZE: usize = 500;
+
+/// Primary key of the single row in `indexer_state`.
+const INDEXER_STATE_ID: i32 = 0;
+
+/// What a processed block means for the maker registry.
+#[derive(Debug, Default)]
+pub struct BlockUpdate {
+    pub announcements: Vec<TxAnnouncement>,
+    /// Registered bond outpoints spent in the block.
+    pub spent_bonds: Vec<OutPoint>,
+}
+
 pub struct Indexer<'a> {
     conn: Arc<Pool<ConnectionManager<SqliteConnection>>>,
     rpc: &'a BitcoinRpc,
+    mode: IndexMode,
+    network: Network,
+    legacy_announcements: bool,
+    /// Bonds announced in indexed blocks that the DB manager, the only
+    /// writer of `fidelity_bonds`, has not stored yet. Their spends are
+    /// reported too, so a bond spent right after its announcement is not
+    /// missed.
+    announced: HashSet<OutPoint>,
 }
 
 impl<'a> Indexer<'a> {
-    pub fn new(conn: Arc<Pool<ConnectionManager<SqliteConnection>>>, rpc: &'a BitcoinRpc) -> Self {
-        Self { conn, rpc }
+    pub fn new(
+        conn: Arc<Pool<ConnectionManager<SqliteConnection>>>,
+        rpc: &'a BitcoinRpc,
+        mode: IndexMode,
+        network: Network,
+        legacy_announcements: bool,
+    ) -> Self {
+        Self {
+            conn,
+            rpc,
+            mode,
+            network,
+            legacy_announcements,
+            announced: HashSet::new(),
+        }
+    }
+
+    /// Height of the next block to index, as persisted by the last committed block.
+    pub fn next_height(&self) -> Result<u64, TrackerError> {
+        let mut conn = self.conn.get()?;
+        let next_height = indexer_state::table
+            .find(INDEXER_STATE_ID)
+            .select(indexer_state::next_height)
+            .first::<i64>(&mut conn)
+            .optional()?;
+        Ok(next_height.unwrap_or(0) as u64)
     }
 
-    pub fn process_mempool(&mut self) {
-        let txids = self.rpc.get_raw_mempool().unwrap();
-        let mut conn = self.conn.get().expect("Failed to get DB connection");
+    /// Re-fetches the amounts flagged in `utxo_rechecks`, which may have been
+    /// stored wrapped to 32 bits before amounts became 64-bit. Confirmed
+    /// outputs are read from their block, one block at a time. Unconfirmed
+    /// ones whose transaction left the mempool stay flagged until the pruner
+    /// drops them.
+    ///
+    /// Returns how many amounts were rewritten.
+    pub fn recheck_amounts(&self) -> Result<usize, TrackerError> {
+        let mut conn = self.conn.get()?;
+        let flagged = utxo_rechecks::table.load::<(Vec<u8>, i32)>(&mut conn)?;
+
+        let mut by_height: BTreeMap<Option<i32>, Vec<(Vec<u8>, i32)>> = BTreeMap::new();
+        for (txid, vout) in flagged {
+            let block_height = utxos::table
+                .find((&txid, vout))
+                .select(utxos::block_height)
+                .first::<Option<i32>>(&mut conn)
+                .optional()?;
+            match block_height {
+                Some(block_height) => by_height
+                    .entry(block_height)
+                    .or_default()
+                    .push((txid, vout)),
+                // The output was pruned since.
+                None => {
+                    diesel::delete(utxo_rechecks::table.find((txid, vout))).execute(&mut conn)?;
+                }
+            }
+        }
 
+        let mut rechecked = 0;
+        for (block_height, outputs) in by_height {
+            let txs: HashMap<Txid, Transaction> = match block_height {
+                Some(height) => {
+                    let block = self
+                        .rpc
+                        .get_block(self.rpc.get_block_hash(height as u64)?)?;
+                    block
+                        .txdata
+                        .into_iter()
+                        .map(|tx| (tx.compute_txid(), tx))
+                        .collect()
+                }
+                None => outputs
+                    .iter()
+                    .filter_map(|(txid, _)| txid_from_bytes(txid))
+                    .filter_map(|txid| Some((txid, self.rpc.get_raw_tx(&txid).ok()?)))
+                    .collect(),
+            };
+            rechecked += conn.transaction::<_, TrackerError, _>(|conn| {
+                let mut rewritten = 0;
+                for (txid, vout) in &outputs {
+                    let Some(out) = txid_from_bytes(txid)
+                        .and_then(|txid| txs.get(&txid))
+                        .and_then(|tx| tx.output.get(*vout as usize))
+                    else {
+                        continue;
+                    };
+                    diesel::update(utxos::table.find((txid, vout)))
+                        .set(utxos::value.eq(out.value.to_sat() as i64))
+                        .execute(conn)?;
+                    diesel::delete(utxo_rechecks::table.find((txid, vout))).execute(conn)?;
+                    rewritten += 1;
+                }
+                Ok(rewritten)
+            })?;
+        }
+        Ok(rechecked)
+    }
+
+    /// Indexes transactions that entered the mempool since the last call.
+    ///
+    /// Returns the registered bond outpoints they spend.
+    pub fn process_mempool(&mut self) -> Result<Vec<OutPoint>, TrackerError> {
+        let txids = self.rpc.get_raw_mempool()?;
+        let mut conn = self.conn.get()?;
+        let watched = load_watched(&mut conn)?;
+        let bonds = self.load_bonds(&mut conn)?;
+
+        let known: HashSet<String> = mempool_tx::table
+            .select(mempool_tx::txid)
+            .load::<String>(&mut conn)?
+            .into_iter()
+            .collect();
+
+        let mut txs = Vec::new();
         for txid in txids {
-            let tx = self.rpc.get_raw_tx(&txid).unwrap();
+            if known.contains(&txid.to_string()) {
+                continue;
+            }
+            // The transaction may have left the mempool since `getrawmempool`.
+            if let Ok(tx) = self.rpc.get_raw_tx(&txid) {
+                txs.push(tx);
+            }
+        }
 
-            self.insert_mempool_tx(&txid.to_string());
+        if txs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut mempool_txs = Vec::with_capacity(txs.len());
+        let mut outputs = Vec::new();
+        for tx in &txs {
+            mempool_txs.push(MempoolTx {
+                txid: tx.compute_txid().to_string(),
+                seen_at: Utc::now().naive_utc(),
+            });
+            outputs.extend(self.tx_outputs(tx, None, &watched));
+        }
 
-            for input in &tx.input {
-                let prevout = &input.previous_output;
+        conn.transaction::<_, TrackerError, _>(|conn| {
+            for chunk in mempool_txs.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_or_ignore_into(mempool_tx::table)
+                    .values(chunk)
+                    .execute(conn)?;
+            }
+            for chunk in outputs.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_or_ignore_into(utxos::table)
+                    .values(chunk)
+                    .execute(conn)?;
+            }
+            let mut inputs = Vec::new();
+            let mut spent_bonds = Vec::new();
+            for tx in &txs {
+                let txid = tx.compute_txid();
+                for input in &tx.input {
+                    let prevout = &input.previous_output;
+                    let bond = bonds.contains(prevout);
+                    if bond {
+                        spent_bonds.push(*prevout);
+                    }
+                    let tracked = mark_utxo_spent(conn, prevout, &txid, None)?
+                        || watched.contains(prevout)
+                        || bond;
+                    if self.mode == IndexMode::Full || tracked {
+                        inputs.push(MempoolInput {
+                            txid: txid.to_string(),
+                            input_txid: prevout.txid.to_string(),
+                            input_vout: prevout.vout as i32,
+                        });
+                    }
+                }
+            }
+            for chunk in inputs.chunks(INSERT_CHUNK_SIZE) {
                 diesel::insert_into(mempool_inputs::table)
-                    .values(&MempoolInput {
-                        txid: txid.to_string(),
-                        input_txid: prevout.txid.to_string(),
-                        input_vout: prevout.vout as i32,
-                    })
-                    .execute(&mut conn)
-                    .unwrap();
-
-                self.mark_utxo_spent(
-                    &txid.to_string(),
-                    prevout.vout as i32,
-                    Some(&txid.to_string()),
-                    false,
-                );
+                    .values(chunk)
+                    .execute(conn)?;
             }
+            Ok(spent_bonds)
+        })
+    }
 
-            for (vout, out) in tx.output.iter().enumerate() {
-                let utxo = Utxo {
-                    txid: txid.to_string().clone(),
-                    vout: vout as i32,
-                    value: out.value.to_sat() as i32,
-                    script_pubkey: out.script_pubkey.to_hex_string(),
-                    confirmed: false,
-                    spent: false,
-                    spent_by_txid: None,
-                    block_height: None,
-                };
-                diesel::insert_or_ignore_into(utxos::table)
-                    .values(&utxo)
-                    .execute(&mut conn)
-                    .unwrap();
+    /// Finds the maker announcements in a block and the registered bonds it
+    /// spends. These are handed to the DB manager before the block is applied
+    /// with `process_block`, so the height never moves past a block whose
+    /// registry updates were not handled.
+    pub fn scan_block(&mut self, height: u64, block: &Block) -> Result<BlockUpdate, TrackerError> {
+        let mut conn = self.conn.get()?;
+        let bonds = self.load_bonds(&mut conn)?;
+        let spent_bonds: Vec<OutPoint> = block
+            .txdata
+            .iter()
+            .flat_map(|tx| &tx.input)
+            .map(|input| input.previous_output)
+            .filter(|prevout| bonds.contains(prevout))
+            .collect();
+
+        let mut announcements: Vec<TxAnnouncement> = block
+            .txdata
+            .iter()
+            .filter_map(|tx| self.find_announcement(tx))
+            .collect();
+        for announcement in &mut announcements {
+            if let TxAnnouncement::Signed { bond, .. } = announcement {
+                bond.conf_height = Some(height as u32);
             }
         }
+
+        self.announced.extend(
+            announcements
+                .iter()
+                .filter_map(|announcement| Some(announcement.bond()?.outpoint)),
+        );
+        Ok(BlockUpdate {
+            announcements,
+            spent_bonds,
+        })
     }
 
-    pub fn process_block(&mut self, height: u64) {
-        let block_hash = self.rpc.get_block_hash(height).unwrap();
-        let block = self.rpc.get_block(block_hash).unwrap();
-        let mut conn = self.conn.get().expect("Failed to get DB connection");
-
-        for tx in block.txdata.iter() {
-            for input in &tx.input {
-                let prevout = &input.previous_output;
-                self.mark_utxo_spent(
-                    &prevout.txid.to_string(),
-                    prevout.vout as i32,
-                    Some(&tx.compute_txid().to_string()),
-                    true,
-                );
-            }
+    /// Applies a block, then advances the indexer height. Everything is
+    /// committed in a single transaction, so a crash never leaves a partially
+    /// applied block behind.
+    pub fn process_block(&mut self, height: u64, block: &Block) -> Result<(), TrackerError> {
+        let mut conn = self.conn.get()?;
+        let watched = load_watched(&mut conn)?;
+        let outputs: Vec<Utxo> = block
+            .txdata
+            .iter()
+            .flat_map(|tx| self.tx_outputs(tx, Some(height), &watched))
+            .collect();
+        let txids: Vec<Vec<u8>> = block
+            .txdata
+            .iter()
+            .map(|tx| txid_to_bytes(&tx.compute_txid()))
+            .collect();
+        let confirmed: Vec<String> = block
+            .txdata
+            .iter()
+            .map(|tx| tx.compute_txid().to_string())
+            .collect();
 
-            for (vout, out) in tx.output.iter().enumerate() {
-                let utxo = Utxo {
-                    txid: tx.compute_txid().to_string(),
-                    vout: vout as i32,
-                    value: out.value.to_sat() as i32,
-                    script_pubkey: out.script_pubkey.to_hex_string(),
-                    confirmed: true,
-                    spent: false,
-                    spent_by_txid: None,
-                    block_height: Some(height as i32),
-                };
+        conn.transaction::<_, TrackerError, _>(|conn| {
+            // Outputs go in first so that spends of outputs created earlier in
+            // the same block find their row. Outputs first seen in the mempool
+            // are only confirmed, keeping any mempool spend recorded for them.
+            for chunk in outputs.chunks(INSERT_CHUNK_SIZE) {
                 diesel::insert_or_ignore_into(utxos::table)
-                    .values(&utxo)
-                    .execute(&mut conn)
-                    .unwrap();
+                    .values(chunk)
+                    .execute(conn)?;
+            }
+            for chunk in txids.chunks(INSERT_CHUNK_SIZE) {
+                diesel::update(
+                    utxos::table
+                        .filter(utxos::txid.eq_any(chunk))
+                        .filter(utxos::block_height.is_null()),
+                )
+                .set((
+                    utxos::confirmed.eq(true),
+                    utxos::block_height.eq(height as i32),
+                ))
+                .execute(conn)?;
             }
+            // Confirmed transactions have left the mempool.
+            for chunk in confirmed.chunks(INSERT_CHUNK_SIZE) {
+                diesel::delete(mempool_inputs::table.filter(mempool_inputs::txid.eq_any(chunk)))
+                    .execute(conn)?;
+                diesel::delete(mempool_tx::table.filter(mempool_tx::txid.eq_any(chunk)))
+                    .execute(conn)?;
+            }
+            for tx in &block.txdata {
+                let spent_by = tx.compute_txid();
+                for input in &tx.input {
+                    mark_utxo_spent(conn, &input.previous_output, &spent_by, Some(height))?;
+                }
+            }
+            diesel::replace_into(indexer_state::table)
+                .values(&IndexerState {
+                    id: INDEXER_STATE_ID,
+                    next_height: height as i64 + 1,
+                })
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Stored bonds plus the announced ones not stored yet.
+    fn load_bonds(
+        &mut self,
+        conn: &mut SqliteConnection,
+    ) -> Result<HashSet<OutPoint>, TrackerError> {
+        let mut bonds = load_bonds(conn)?;
+        self.announced.retain(|outpoint| !bonds.contains(outpoint));
+        bonds.extend(&self.announced);
+        Ok(bonds)
+    }
+
+    fn find_announcement(&self, tx: &Transaction) -> Option<TxAnnouncement> {
+        find_announcement(tx, self.network, self.legacy_announcements)
+    }
+
+    /// Builds the rows to store for `tx`'s outputs. In light mode only the
+    /// bond output and the announcement itself are kept from announcement
+    /// transactions, plus any explicitly watched outpoint.
+    fn tx_outputs(
+        &self,
+        tx: &Transaction,
+        height: Option<u64>,
+        watched: &HashSet<OutPoint>,
+    ) -> Vec<Utxo> {
+        let txid = tx.compute_txid();
+        let announcement = match self.mode {
+            IndexMode::Full => None,
+            IndexMode::Light => self.find_announcement(tx),
+        };
+        let bond = announcement.as_ref().and_then(TxAnnouncement::bond);
+        tx.output
+            .iter()
+            .enumerate()
+            .filter(|(vout, out)| {
+                let outpoint = OutPoint::new(txid, *vout as u32);
+                match self.mode {
+                    IndexMode::Full => true,
+                    IndexMode::Light => {
+                        bond.is_some_and(|bond| bond.outpoint == outpoint)
+                            || (announcement.is_some() && out.script_pubkey.is_op_return())
+                            || watched.contains(&outpoint)
+                    }
+                }
+            })
+            .map(|(vout, out)| Utxo::new(&OutPoint::new(txid, vout as u32), out, height))
+            .collect()
+    }
+}
+
+/// Looks up an output in `utxos`, falling back to `gettxout` for outputs that
+/// were never stored: those below the start height, or skipped in light mode.
+/// Outputs whose amount awaits a recheck are looked up through `gettxout` too.
+pub(crate) fn lookup_utxo(
+    conn: &mut SqliteConnection,
+    rpc: &BitcoinRpc,
+    outpoint: &OutPoint,
+) -> Result<Option<Utxo>, TrackerError> {
+    let (txid, vout) = (txid_to_bytes(&outpoint.txid), outpoint.vout as i32);
+    let stored = utxos::table
+        .find((&txid, vout))
+        .first::<Utxo>(conn)
+        .optional()?;
+    let unverified = diesel::select(exists(utxo_rechecks::table.find((&txid, vout))))
+        .get_result::<bool>(conn)?;
+    if stored.is_some() && !unverified {
+        return Ok(stored);
+    }
+
+    let Some(tx_out) = rpc.get_tx_out(outpoint)? else {
+        return Ok(None);
+    };
+    let block_height = match tx_out.confirmations {
+        0 => None,
+        confirmations => Some(rpc.get_block_height(&tx_out.bestblock)? + 1 - confirmations as u64),
+    };
+    let out = TxOut {
+        value: tx_out.value,
+        script_pubkey: ScriptBuf::from_bytes(tx_out.script_pub_key.hex),
+    };
+    Ok(Some(Utxo::new(outpoint, &out, block_height)))
+}
+
+fn load_bonds(conn: &mut SqliteConnection) -> Result<HashSet<OutPoint>, TrackerError> {
+    let rows = fidelity_bonds::table
+        .select((fidelity_bonds::txid, fidelity_bonds::vout))
+        .load::<(Vec<u8>, i32)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(txid, vout)| Some(OutPoint::new(txid_from_bytes(&txid)?, vout as u32)))
+        .collect())
+}
+
+fn load_watched(conn: &mut SqliteConnection) -> Result<HashSet<OutPoint>, TrackerError> {
+    let rows = watched_outpoints::table
+        .select((watched_outpoints::txid, watched_outpoints::vout))
+        .load::<(Vec<u8>, i32)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(txid, vout)| Some(OutPoint::new(txid_from_bytes(&txid)?, vout as u32)))
+        .collect())
+}
+
+/// Returns whether a stored output was spent. `spent_height` is `None` for
+/// mempool spends.
+fn mark_utxo_spent(
+    conn: &mut SqliteConnection,
+    outpoint: &OutPoint,
+    spent_by: &Txid,
+    spent_height: Option<u64>,
+) -> Result<bool, TrackerError> {
+    use utxos::dsl;
+
+    let updated = diesel::update(
+        dsl::utxos.filter(
+            dsl::txid
+                .eq(txid_to_bytes(&outpoint.txid))
+                .and(dsl::vout.eq(outpoint.vout as i32)),
+        ),
+    )
+    .set((
+        dsl::spent.eq(true),
+        dsl::spent_by_txid.eq(txid_to_bytes(spent_by)),
+        dsl::confirmed.eq(spent_height.is_some()),
+        dsl::spent_height.eq(spent_height.map(|h| h as i32)),
+    ))
+    .execute(conn)?;
+    Ok(updated > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::announcement::test_utils::*;
+    use crate::test_utils::file_pool;
+    use bitcoincore_rpc::bitcoin::{
+        Amount, Block, Network, ScriptBuf, TxIn, TxOut, absolute::LockTime, blockdata::constants,
+        hashes::Hash, transaction::Version,
+    };
+
+    fn test_rpc() -> BitcoinRpc {
+        BitcoinRpc::new("http://127.0.0.1:1".into(), "user".into(), "pass".into()).unwrap()
+    }
+
+    fn tx(inputs: Vec<OutPoint>, lock_time: LockTime, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output,
+        }
+    }
+
+    fn p2wpkh_payment(value: u64, tag: u32) -> TxOut {
+        let mut program = [0u8; 22];
+        program[1] = 0x14;
+        program[2..6].copy_from_slice(&tag.to_le_bytes());
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::from_bytes(program.to_vec()),
         }
     }
 
-    fn insert_mempool_tx(&mut self, txid: &str) {
-        let mut conn = self.conn.get().expect("Failed to get DB connection");
-        diesel::insert_or_ignore_into(mempool_tx::table)
+    fn announcement_tx() -> Transaction {
+        let announcement = signed_announcement(
+            Network::Regtest,
+            6102,
+            &bond_key(),
+            LockTime::from_height(900_000).unwrap(),
+        );
+        tx(
+            vec![OutPoint::null()],
+            LockTime::from_height(100).unwrap(),
+            vec![
+                bond_txout(&announcement, Amount::from_sat(50_000_000)),
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: announcement_script(announcement.to_payload()),
+                },
+            ],
+        )
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        let mut block = constants::genesis_block(Network::Regtest);
+        block.txdata = txdata;
+        block
+    }
+
+    #[test]
+    fn test_process_block_applies_spends_and_height() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = file_pool(&dir);
+        let rpc = test_rpc();
+        let mut indexer =
+            Indexer::new(pool.clone(), &rpc, IndexMode::Full, Network::Regtest, false);
+
+        let funding = tx(
+            vec![],
+            LockTime::ZERO,
+            vec![p2wpkh_payment(1_000, 0), p2wpkh_payment(2_000, 1)],
+        );
+        let spend = tx(
+            vec![OutPoint::new(funding.compute_txid(), 0)],
+            LockTime::ZERO,
+            vec![p2wpkh_payment(900, 2)],
+        );
+
+        let block = block(vec![funding.clone(), spend.clone()]);
+        let update = indexer.scan_block(7, &block).unwrap();
+        assert!(update.announcements.is_empty());
+        assert!(update.spent_bonds.is_empty());
+        indexer.process_block(7, &block).unwrap();
+
+        let mut conn = pool.get().unwrap();
+        let rows = utxos::table.load::<Utxo>(&mut conn).unwrap();
+        assert_eq!(rows.len(), 3);
+        let spent = rows
+            .iter()
+            .find(|u| u.outpoint() == Some(OutPoint::new(funding.compute_txid(), 0)))
+            .unwrap();
+        assert!(spent.spent);
+        assert_eq!(spent.spent_by(), Some(spend.compute_txid()));
+        assert_eq!(indexer.next_height().unwrap(), 8);
+    }
+
+    #[test]
+    fn test_confirmation_keeps_mempool_spend() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = file_pool(&dir);
+        let rpc = test_rpc();
+        let mut indexer =
+            Indexer::new(pool.clone(), &rpc, IndexMode::Full, Network::Regtest, false);
+
+        // Both transactions were seen in the mempool, but only the funding one
+        // makes it into the block.
+        let funding = tx(vec![], LockTime::ZERO, vec![p2wpkh_payment(1_000, 0)]);
+        let output = OutPoint::new(funding.compute_txid(), 0);
+        let spender = Txid::from_byte_array([7; 32]);
+        let mut conn = pool.get().unwrap();
+        diesel::insert_into(utxos::table)
+            .values(&Utxo::new(&output, &funding.output[0], None))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(mark_utxo_spent(&mut conn, &output, &spender, None).unwrap());
+        diesel::insert_into(mempool_tx::table)
             .values(&MempoolTx {
-                txid: txid.to_string(),
-                seen_at: NaiveDateTime::MIN,
+                txid: funding.compute_txid().to_string(),
+                seen_at: Utc::now().naive_utc(),
             })
             .execute(&mut conn)
             .unwrap();
+
+        indexer.process_block(5, &block(vec![funding])).unwrap();
+        let pending = mempool_tx::table.count().get_result::<i64>(&mut conn);
+        assert_eq!(pending.unwrap(), 0);
+        let row = utxos::table.load::<Utxo>(&mut conn).unwrap().remove(0);
+        assert!(row.confirmed);
+        assert_eq!(row.block_height, Some(5));
+        assert!(row.spent);
+        assert_eq!(row.spent_by(), Some(spender));
+        assert_eq!(row.spent_height, None);
     }
 
-    fn mark_utxo_spent(
-        &mut self,
-        txid: &str,
-        vout: i32,
-        spent_by: Option<&str>,
-        is_confirmed_spend: bool,
-    ) {
-        use utxos::dsl;
-        let mut conn = self.conn.get().expect("Failed to get DB connection");
-
-        let confirmed_value = is_confirmed_spend;
-
-        diesel::update(dsl::utxos.filter(dsl::txid.eq(txid).and(dsl::vout.eq(vout))))
-            .set((
-                dsl::spent.eq(true),
-                dsl::spent_by_txid.eq(spent_by.map(str::to_string)),
-                dsl::confirmed.eq(confirmed_value),
-            ))
-            .execute(&mut conn)
+    #[test]
+    fn test_spent_bond_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = file_pool(&dir);
+        let rpc = test_rpc();
+        let mut indexer = Indexer::new(
+            pool.clone(),
+            &rpc,
+            IndexMode::Light,
+            Network::Regtest,
+            false,
+        );
+
+        let announced = block(vec![announcement_tx()]);
+        let update = indexer.scan_block(1, &announced).unwrap();
+        indexer.process_block(1, &announced).unwrap();
+        let bond = update.announcements[0].bond().unwrap().outpoint;
+        assert!(update.spent_bonds.is_empty());
+
+        // Reported even though the DB manager has not stored the bond yet.
+        let sweep = block(vec![tx(
+            vec![bond],
+            LockTime::ZERO,
+            vec![p2wpkh_payment(900, 0)],
+        )]);
+        let update = indexer.scan_block(2, &sweep).unwrap();
+        assert_eq!(update.spent_bonds, vec![bond]);
+    }
+
+    #[test]
+    fn test_light_mode_uses_less_disk_than_full_mode() {
+        let watched_tx = tx(vec![], LockTime::ZERO, vec![p2wpkh_payment(7_000, 7)]);
+        let watched = OutPoint::new(watched_tx.compute_txid(), 0);
+        let announcement = announcement_tx();
+
+        let blocks: Vec<Block> = (0..20u32)
+            .map(|height| {
+                let mut txdata: Vec<Transaction> = (0..50u32)
+                    .map(|i| {
+                        let tag = height * 1_000 + i * 4;
+                        tx(
+                            vec![],
+                            LockTime::ZERO,
+                            (0..4).map(|o| p2wpkh_payment(10_000, tag + o)).collect(),
+                        )
+                    })
+                    .collect();
+                if height == 3 {
+                    txdata.push(announcement.clone());
+                    txdata.push(watched_tx.clone());
+                }
+                block(txdata)
+            })
+            .collect();
+
+        let index = |mode: IndexMode| {
+            let dir = tempfile::tempdir().unwrap();
+            let pool = file_pool(&dir);
+            let rpc = test_rpc();
+            let mut conn = pool.get().unwrap();
+            diesel::insert_into(watched_outpoints::table)
+                .values(&crate::db::model::WatchedOutpoint::from(&watched))
+                .execute(&mut conn)
+                .unwrap();
+
+            let mut indexer = Indexer::new(pool.clone(), &rpc, mode, Network::Regtest, false);
+            let mut announced = Vec::new();
+            let mut bonds = Vec::new();
+            for (height, block) in blocks.iter().enumerate() {
+                let found = indexer.scan_block(height as u64, block).unwrap();
+                indexer.process_block(height as u64, block).unwrap();
+                announced.extend(found.announcements.iter().map(TxAnnouncement::address));
+                bonds.extend(found.announcements.iter().filter_map(|a| a.bond().cloned()));
+            }
+            assert_eq!(bonds.len(), 1);
+            assert_eq!(
+                bonds[0].outpoint,
+                OutPoint::new(announcement.compute_txid(), 0)
+            );
+            assert_eq!(bonds[0].conf_height, Some(3));
+            // Storing the bond is left to the DB manager.
+            let stored = fidelity_bonds::table.count().get_result::<i64>(&mut conn);
+            assert_eq!(stored.unwrap(), 0);
+
+            let rows = utxos::table.count().get_result::<i64>(&mut conn).unwrap();
+            // Move the write-ahead log into the database file before measuring it.
+            diesel::connection::SimpleConnection::batch_execute(
+                &mut *conn,
+                "PRAGMA wal_checkpoint(TRUNCATE);",
+            )
             .unwrap();
+            let size = std::fs::metadata(dir.path().join("tracker.db"))
+                .unwrap()
+                .len();
+            (announced, rows, size)
+        };
+
+        let (full_announced, full_rows, full_size) = index(IndexMode::Full);
+        let (light_announced, light_rows, light_size) = index(IndexMode::Light);
+
+        assert_eq!(full_announced, vec![format!("{}:6102", TEST_HOST.1)]);
+        assert_eq!(light_announced, full_announced);
+        assert_eq!(full_rows, 20 * 50 * 4 + 3);
+        // Bond output, announcement output and the watched outpoint.
+        assert_eq!(light_rows, 3);
+        assert!(
+            light_size * 4 < full_size,
+            "light: {light_size} bytes, full: {full_size} bytes"
+        );
     }
 }
@@ -1,29 +1,49 @@
 #![allow(warnings)]
 use bitcoincore_rpc::{Auth, Client};
 use diesel::SqliteConnection;
-use diesel::r2d2::ConnectionManager;
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection};
 use r2d2::Pool;
 use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tracing::{error, info, warn};
 
+use crate::db::RegistrationPolicy;
+use crate::server::ServerConfig;
 use crate::status::{State, Status};
-use crate::types::DbRequest;
+use crate::types::{DbRequest, RegistryEvent};
 
+mod admin;
 mod db;
 mod error;
 mod handle_error;
 mod indexer;
+mod metrics;
+mod proxy;
 mod server;
 mod status;
+#[cfg(test)]
+mod test_utils;
 mod tor;
 mod types;
 mod utils;
 
+pub use db::SybilPolicy;
+pub use db::lifecycle::Lifecycle;
+pub use db::policy::ListingPolicy;
+pub use db::pruner::RetentionPolicy;
+pub use indexer::IndexMode;
+pub use proxy::SocksProxy;
+pub use server::{ProbeSchedule, StreamIsolation};
+
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+/// Registry changes buffered for a slow subscriber before it lags and has to
+/// reload the whole registry.
+const REGISTRY_EVENTS_CAPACITY: usize = 1024;
+
 #[cfg(not(feature = "integration-test"))]
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -32,8 +52,20 @@ pub struct Config {
     pub address: String,
     pub control_port: u16,
     pub tor_auth_password: String,
-    pub socks_port: u16,
+    /// Proxy for every outbound connection, checked at startup.
+    pub socks_proxy: SocksProxy,
     pub datadir: String,
+    pub index_mode: IndexMode,
+    pub retention: RetentionPolicy,
+    pub start_height: Option<u64>,
+    pub legacy_announcements: bool,
+    pub sybil_policy: SybilPolicy,
+    pub admin_address: Option<String>,
+    pub min_bond_confirmations: u32,
+    /// JSON file with the `ListingPolicy`, reloadable through the admin interface.
+    pub policy_file: Option<String>,
+    pub probe_schedule: ProbeSchedule,
+    pub lifecycle: Lifecycle,
 }
 
 #[cfg(feature = "integration-test")]
@@ -43,6 +75,48 @@ pub struct Config {
     pub rpc_auth: Auth,
     pub address: String,
     pub datadir: String,
+    pub index_mode: IndexMode,
+    pub retention: RetentionPolicy,
+    pub start_height: Option<u64>,
+    pub legacy_announcements: bool,
+    pub sybil_policy: SybilPolicy,
+    pub admin_address: Option<String>,
+    pub min_bond_confirmations: u32,
+    /// JSON file with the `ListingPolicy`, reloadable through the admin interface.
+    pub policy_file: Option<String>,
+    pub probe_schedule: ProbeSchedule,
+    pub lifecycle: Lifecycle,
+}
+
+/// Pragmas applied to every pooled connection. WAL lets the db manager read
+/// while the indexer holds a write transaction for a block. The busy timeout
+/// is set first, so connections the pool opens at once wait for each other
+/// while switching to WAL instead of failing.
+#[derive(Debug)]
+struct SqlitePragmas;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqlitePragmas {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(
+            "PRAGMA busy_timeout = 5000;
+             PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             PRAGMA temp_store = MEMORY;",
+        )
+        .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Pool over the database at `database_url`, with `SqlitePragmas` applied to
+/// every connection.
+fn open_pool(database_url: &str) -> Arc<Pool<ConnectionManager<SqliteConnection>>> {
+    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
+    Arc::new(
+        Pool::builder()
+            .connection_customizer(Box::new(SqlitePragmas))
+            .build(manager)
+            .expect("Failed to create DB pool"),
+    )
 }
 
 fn run_migrations(pool: Arc<Pool<ConnectionManager<SqliteConnection>>>) {
@@ -59,12 +133,7 @@ pub async fn start(cfg: Config) {
     if let Some(parent) = Path::new(&database_url).parent() {
         std::fs::create_dir_all(parent).expect("Failed to create database directory");
     }
-    let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-    let pool = Arc::new(
-        Pool::builder()
-            .build(manager)
-            .expect("Failed to create DB pool"),
-    );
+    let pool = open_pool(&database_url);
     run_migrations(pool.clone());
     info!("Connected to indexer db");
 
@@ -73,6 +142,21 @@ pub async fn start(cfg: Config) {
         .await
         .expect("Failed to check Tor status");
 
+    #[cfg(not(feature = "integration-test"))]
+    {
+        // Tor isolates streams by their SOCKS credentials, which a proxy
+        // login leaves no room for.
+        assert!(
+            !cfg.socks_proxy.has_auth() || cfg.probe_schedule.isolation == StreamIsolation::None,
+            "Probe stream isolation needs a SOCKS proxy without login, set --tor-isolation none to use {}",
+            cfg.socks_proxy
+        );
+        cfg.socks_proxy
+            .check()
+            .await
+            .expect("Failed to reach the SOCKS proxy");
+    }
+
     #[cfg(not(feature = "integration-test"))]
     let hostname = match cfg.address.split_once(':') {
         Some((_, port)) => {
@@ -99,21 +183,69 @@ pub async fn start(cfg: Config) {
 
     let (mut db_tx, db_rx) = mpsc::channel::<DbRequest>(10);
     let (status_tx, mut status_rx) = mpsc::channel::<Status>(10);
+    let (events_tx, _) = broadcast::channel::<RegistryEvent>(REGISTRY_EVENTS_CAPACITY);
 
     let rpc_client = Client::new(&cfg.rpc_url, cfg.rpc_auth.clone()).unwrap();
 
-    spawn_db_manager(pool.clone(), db_rx, status_tx.clone()).await;
-    spawn_mempool_indexer(pool.clone(), db_tx.clone(), status_tx.clone(), rpc_client).await;
-    spawn_server(
+    let mut listing_policy = match &cfg.policy_file {
+        Some(path) => ListingPolicy::load(Path::new(path)).expect("Failed to load listing policy"),
+        None => ListingPolicy::default(),
+    };
+
+    let registration = RegistrationPolicy {
+        sybil: cfg.sybil_policy,
+        min_bond_confirmations: cfg.min_bond_confirmations,
+    };
+    spawn_db_manager(
+        pool.clone(),
+        db_rx,
+        status_tx.clone(),
+        registration,
+        listing_policy.clone(),
+        cfg.lifecycle.clone(),
+        events_tx.clone(),
+    )
+    .await;
+    spawn_pruner(
+        pool.clone(),
+        db_tx.clone(),
+        cfg.retention.clone(),
+        status_tx.clone(),
+    )
+    .await;
+    spawn_mempool_indexer(
+        pool.clone(),
         db_tx.clone(),
         status_tx.clone(),
-        cfg.address.clone(),
+        rpc_client,
+        cfg.index_mode,
+        cfg.start_height,
+        cfg.legacy_announcements,
+    )
+    .await;
+    let server_config = ServerConfig {
+        address: cfg.address.clone(),
+        onion_address: hostname,
         #[cfg(not(feature = "integration-test"))]
-        cfg.socks_port,
-        hostname.clone(),
+        proxy: cfg.socks_proxy.clone(),
+        min_bond_confirmations: cfg.min_bond_confirmations,
+        probe_schedule: cfg.probe_schedule.clone(),
+        legacy_announcements: cfg.legacy_announcements,
+    };
+    spawn_server(
+        pool.clone(),
+        db_tx.clone(),
+        status_tx.clone(),
+        Client::new(&cfg.rpc_url, cfg.rpc_auth.clone()).unwrap(),
+        server_config.clone(),
+        events_tx.clone(),
     )
     .await;
 
+    if let Some(admin_address) = cfg.admin_address.clone() {
+        spawn_admin(db_tx.clone(), admin_address, cfg.policy_file.clone()).await;
+    }
+
     info!("Tracker started");
 
     while let Some(status) = status_rx.recv().await {
@@ -125,7 +257,23 @@ pub async fn start(cfg: Config) {
                 );
                 let (new_db_tx, new_db_rx) = mpsc::channel::<DbRequest>(10);
                 db_tx = new_db_tx;
-                spawn_db_manager(pool.clone(), new_db_rx, status_tx.clone()).await;
+                // Pick up reloads made through the admin interface.
+                if let Some(path) = &cfg.policy_file {
+                    match ListingPolicy::load(Path::new(path)) {
+                        Ok(policy) => listing_policy = policy,
+                        Err(e) => warn!("Keeping previous listing policy: {e:?}"),
+                    }
+                }
+                spawn_db_manager(
+                    pool.clone(),
+                    new_db_rx,
+                    status_tx.clone(),
+                    registration,
+                    listing_policy.clone(),
+                    cfg.lifecycle.clone(),
+                    events_tx.clone(),
+                )
+                .await;
             }
             State::Healthy(info) => {
                 info!("System healthy: {:?}", info);
@@ -133,17 +281,36 @@ pub async fn start(cfg: Config) {
             State::MempoolShutdown(err) => {
                 warn!("Mempool Indexer crashed. Restarting... Error: {:?}", err);
                 let client = Client::new(&cfg.rpc_url, cfg.rpc_auth.clone()).unwrap();
-                spawn_mempool_indexer(pool.clone(), db_tx.clone(), status_tx.clone(), client).await;
+                spawn_mempool_indexer(
+                    pool.clone(),
+                    db_tx.clone(),
+                    status_tx.clone(),
+                    client,
+                    cfg.index_mode,
+                    cfg.start_height,
+                    cfg.legacy_announcements,
+                )
+                .await;
+            }
+            State::PrunerShutdown(err) => {
+                warn!("Pruner crashed. Restarting... Error: {:?}", err);
+                spawn_pruner(
+                    pool.clone(),
+                    db_tx.clone(),
+                    cfg.retention.clone(),
+                    status_tx.clone(),
+                )
+                .await;
             }
             State::ServerShutdown(err) => {
                 warn!("Server crashed. Restarting... Error: {:?}", err);
                 spawn_server(
+                    pool.clone(),
                     db_tx.clone(),
                     status_tx.clone(),
-                    cfg.address.clone(),
-                    #[cfg(not(feature = "integration-test"))]
-                    cfg.socks_port,
-                    hostname.clone(),
+                    Client::new(&cfg.rpc_url, cfg.rpc_auth.clone()).unwrap(),
+                    server_config.clone(),
+                    events_tx.clone(),
                 )
                 .await;
             }
@@ -155,9 +322,45 @@ async fn spawn_db_manager(
     pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
     db_rx: tokio::sync::mpsc::Receiver<DbRequest>,
     status_tx: tokio::sync::mpsc::Sender<Status>,
+    registration: RegistrationPolicy,
+    listing_policy: ListingPolicy,
+    lifecycle: Lifecycle,
+    events_tx: broadcast::Sender<RegistryEvent>,
 ) {
     info!("Spawning db manager");
-    tokio::spawn(db::run(pool, db_rx, status::Sender::DBManager(status_tx)));
+    tokio::spawn(db::run(
+        pool,
+        db_rx,
+        status::Sender::DBManager(status_tx),
+        registration,
+        listing_policy,
+        lifecycle,
+        events_tx,
+    ));
+}
+
+async fn spawn_admin(
+    db_tx: tokio::sync::mpsc::Sender<DbRequest>,
+    address: String,
+    policy_file: Option<String>,
+) {
+    info!("Spawning admin interface");
+    tokio::spawn(admin::run(db_tx, address, policy_file));
+}
+
+async fn spawn_pruner(
+    pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
+    db_tx: tokio::sync::mpsc::Sender<DbRequest>,
+    policy: RetentionPolicy,
+    status_tx: tokio::sync::mpsc::Sender<Status>,
+) {
+    info!("Spawning pruner");
+    tokio::spawn(db::pruner::run(
+        pool,
+        db_tx,
+        policy,
+        status::Sender::Pruner(status_tx),
+    ));
 }
 
 async fn spawn_mempool_indexer(
@@ -165,6 +368,9 @@ async fn spawn_mempool_indexer(
     db_tx: tokio::sync::mpsc::Sender<DbRequest>,
     status_tx: tokio::sync::mpsc::Sender<Status>,
     client: Client,
+    mode: IndexMode,
+    start_height: Option<u64>,
+    legacy_announcements: bool,
 ) {
     info!("Spawning indexer");
     tokio::spawn(indexer::run(
@@ -172,23 +378,63 @@ async fn spawn_mempool_indexer(
         db_tx,
         status::Sender::Mempool(status_tx),
         client.into(),
+        mode,
+        start_height,
+        legacy_announcements,
     ));
 }
 
 async fn spawn_server(
+    pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
     db_tx: tokio::sync::mpsc::Sender<DbRequest>,
     status_tx: tokio::sync::mpsc::Sender<Status>,
-    address: String,
-    #[cfg(not(feature = "integration-test"))] socks_port: u16,
-    hostname: String,
+    client: Client,
+    config: ServerConfig,
+    events_tx: broadcast::Sender<RegistryEvent>,
 ) {
     info!("Spawning server instance");
     tokio::spawn(server::run(
+        pool,
         db_tx,
         status::Sender::Server(status_tx),
-        address,
-        #[cfg(not(feature = "integration-test"))]
-        socks_port,
-        hostname,
+        client.into(),
+        config,
+        events_tx,
     ));
 }
+
+#[cfg(test)]
+mod tests {
+    use diesel::{
+        QueryableByName, RunQueryDsl, sql_query,
+        sql_types::{Integer, Text},
+    };
+
+    use crate::test_utils::file_pool;
+
+    #[derive(QueryableByName)]
+    struct Pragmas {
+        #[diesel(sql_type = Text)]
+        journal_mode: String,
+        #[diesel(sql_type = Integer)]
+        synchronous: i32,
+        #[diesel(sql_type = Integer)]
+        timeout: i32,
+    }
+
+    #[test]
+    fn test_pool_applies_pragmas() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conn = file_pool(&dir).get().unwrap();
+        let pragmas = sql_query(
+            "SELECT journal_mode, synchronous, timeout
+             FROM pragma_journal_mode(), pragma_synchronous(), pragma_busy_timeout()",
+        )
+        .get_result::<Pragmas>(&mut conn)
+        .unwrap();
+        assert_eq!(pragmas.journal_mode, "wal");
+        // NORMAL
+        assert_eq!(pragmas.synchronous, 1);
+        assert_eq!(pragmas.timeout, 5000);
+    }
+}
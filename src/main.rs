@@ -1,6 +1,10 @@
 use bitcoincore_rpc::Auth;
 use clap::Parser;
-use tracker::{Config, start};
+use std::time::Duration;
+use tracker::{
+    Config, IndexMode, Lifecycle, ProbeSchedule, RetentionPolicy, SocksProxy, StreamIsolation,
+    SybilPolicy, start,
+};
 
 #[derive(Parser)]
 struct App {
@@ -14,10 +18,75 @@ struct App {
     control_port: u16,
     #[clap(long, default_value = "")]
     tor_auth_password: String,
+    /// Port of a local Tor SOCKS proxy. Ignored if `--socks-proxy` is set.
     #[clap(long, default_value = "9050")]
     socks_port: u16,
+    /// SOCKS5 proxy for outbound connections, as `socks5://[username:password@]host:port`.
+    #[clap(long)]
+    socks_proxy: Option<SocksProxy>,
     #[clap(long, default_value = ".tracker")]
     datadir: String,
+    /// `full` stores every output, `light` only bonds, announcements and watched outpoints.
+    #[clap(long, default_value = "full")]
+    index_mode: IndexMode,
+    /// Delete spent outputs this many blocks after their spend confirms. Kept forever if unset.
+    #[clap(long)]
+    prune_spent_after: Option<u32>,
+    /// Drop mempool transactions first seen more than this many seconds ago.
+    #[clap(long, default_value = "1209600")]
+    mempool_max_age: u64,
+    /// Seconds between pruning runs.
+    #[clap(long, default_value = "600")]
+    prune_interval: u64,
+    /// Skip blocks below this height. Defaults to a per-network birthday, or to
+    /// genesis with `--legacy-announcements`.
+    #[clap(long)]
+    start_height: Option<u64>,
+    /// Also accept unsigned `OP_RETURN` address announcements from older makers,
+    /// whose pongs are then taken without a signature check.
+    #[clap(long)]
+    legacy_announcements: bool,
+    /// Which address keeps a bond claimed by several makers: `latest-wins` or `first-wins`.
+    #[clap(long, default_value = "latest-wins")]
+    sybil_policy: SybilPolicy,
+    /// Serve metrics and admin endpoints over HTTP on this address. Disabled if unset.
+    #[clap(long)]
+    admin_address: Option<String>,
+    /// Confirmations a bond needs before its maker is accepted through `Post`.
+    /// Bonded makers below it are not listed until their bond is deep enough.
+    #[clap(long, default_value = "6")]
+    min_bond_confirmations: u32,
+    /// JSON listing policy filtering the makers served to takers. Lists everyone if unset.
+    #[clap(long)]
+    policy_file: Option<String>,
+    /// Maker probes in flight at once.
+    #[clap(long, default_value = "16")]
+    probe_concurrency: usize,
+    /// Seconds between two probes of a maker that answers.
+    #[clap(long, default_value = "60")]
+    probe_interval: u64,
+    /// Longest wait, in seconds, between probes of a maker that keeps failing.
+    #[clap(long, default_value = "3600")]
+    probe_max_backoff: u64,
+    /// Seconds allowed to open a connection to a maker.
+    #[clap(long, default_value = "30")]
+    probe_connect_timeout: u64,
+    /// Seconds allowed for a maker to answer a ping.
+    #[clap(long, default_value = "30")]
+    probe_response_timeout: u64,
+    /// Which probes may share a Tor circuit: `per-maker`, `per-sweep` or `none`.
+    /// Must be `none` with a `--socks-proxy` that requires a login.
+    #[clap(long, default_value = "per-maker")]
+    tor_isolation: StreamIsolation,
+    /// Failed probes in a row after which an active maker is degraded.
+    #[clap(long, default_value = "2")]
+    degraded_after: u32,
+    /// Failed probes in a row after which a maker is stale and no longer listed.
+    #[clap(long, default_value = "5")]
+    stale_after: u32,
+    /// Days without an answer after which a maker is evicted and no longer probed.
+    #[clap(long, default_value = "30")]
+    evict_after_days: u64,
 }
 
 #[tokio::main]
@@ -25,6 +94,27 @@ async fn main() {
     tracing_subscriber::fmt::init();
     let args = App::parse();
 
+    let retention = RetentionPolicy {
+        spent_depth: args.prune_spent_after,
+        mempool_max_age: Duration::from_secs(args.mempool_max_age),
+        interval: Duration::from_secs(args.prune_interval),
+    };
+
+    let probe_schedule = ProbeSchedule {
+        concurrency: args.probe_concurrency,
+        interval: Duration::from_secs(args.probe_interval),
+        max_backoff: Duration::from_secs(args.probe_max_backoff),
+        connect_timeout: Duration::from_secs(args.probe_connect_timeout),
+        response_timeout: Duration::from_secs(args.probe_response_timeout),
+        isolation: args.tor_isolation,
+    };
+
+    let lifecycle = Lifecycle {
+        degraded_after: args.degraded_after,
+        stale_after: args.stale_after,
+        evict_after: Duration::from_secs(args.evict_after_days * 24 * 60 * 60),
+    };
+
     let (user, pass) = {
         let parts: Vec<_> = args.auth.split(':').collect();
         (parts[0].to_string(), parts[1].to_string())
@@ -37,8 +127,20 @@ async fn main() {
         address: args.address,
         control_port: args.control_port,
         tor_auth_password: args.tor_auth_password,
-        socks_port: args.socks_port,
+        socks_proxy: args
+            .socks_proxy
+            .unwrap_or_else(|| SocksProxy::local(args.socks_port)),
         datadir: args.datadir,
+        index_mode: args.index_mode,
+        retention,
+        start_height: args.start_height,
+        legacy_announcements: args.legacy_announcements,
+        sybil_policy: args.sybil_policy,
+        admin_address: args.admin_address,
+        min_bond_confirmations: args.min_bond_confirmations,
+        policy_file: args.policy_file,
+        probe_schedule,
+        lifecycle,
     };
 
     #[cfg(feature = "integration-test")]
@@ -47,6 +149,16 @@ async fn main() {
         rpc_auth: Auth::UserPass(user, pass),
         address: args.address,
         datadir: args.datadir,
+        index_mode: args.index_mode,
+        retention,
+        start_height: args.start_height,
+        legacy_announcements: args.legacy_announcements,
+        sybil_policy: args.sybil_policy,
+        admin_address: args.admin_address,
+        min_bond_confirmations: args.min_bond_confirmations,
+        policy_file: args.policy_file,
+        probe_schedule,
+        lifecycle,
     };
 
     start(cfg).await;
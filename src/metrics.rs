@@ -0,0 +1,157 @@
+//! Process-wide counters, rendered in the Prometheus text format by the admin
+//! interface.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    db::probes::ProbeStats,
+    types::{MakerVersion, ServerInfo},
+};
+
+pub(crate) struct Metrics {
+    /// Registrations whose bond outpoint or pubkey already backed another
+    /// active address.
+    pub sybil_conflicts: AtomicU64,
+    /// Liveness probes answered and failed.
+    pub probe_successes: AtomicU64,
+    pub probe_failures: AtomicU64,
+}
+
+pub(crate) static METRICS: Metrics = Metrics {
+    sybil_conflicts: AtomicU64::new(0),
+    probe_successes: AtomicU64::new(0),
+    probe_failures: AtomicU64::new(0),
+};
+
+impl Metrics {
+    /// Renders the counters along with gauges derived from the registry, the
+    /// probe history and the versions reported by the makers currently
+    /// listed.
+    pub fn render(
+        &self,
+        servers: &[(String, ServerInfo)],
+        probes: &HashMap<String, ProbeStats>,
+        versions: &HashMap<String, MakerVersion>,
+    ) -> String {
+        let mut by_state = BTreeMap::new();
+        for (_, info) in servers {
+            *by_state.entry(info.state.as_str()).or_insert(0u64) += 1;
+        }
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP tracker_sybil_conflicts_total Registrations reusing a bond that backs another address."
+        );
+        let _ = writeln!(out, "# TYPE tracker_sybil_conflicts_total counter");
+        let _ = writeln!(
+            out,
+            "tracker_sybil_conflicts_total {}",
+            self.sybil_conflicts.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP tracker_makers Registered makers by state.");
+        let _ = writeln!(out, "# TYPE tracker_makers gauge");
+        for (state, count) in by_state {
+            let _ = writeln!(out, "tracker_makers{{state=\"{state}\"}} {count}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracker_probes_total Liveness probes by result."
+        );
+        let _ = writeln!(out, "# TYPE tracker_probes_total counter");
+        let _ = writeln!(
+            out,
+            "tracker_probes_total{{result=\"success\"}} {}",
+            self.probe_successes.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "tracker_probes_total{{result=\"failure\"}} {}",
+            self.probe_failures.load(Ordering::Relaxed)
+        );
+        let probes: BTreeMap<_, _> = probes.iter().collect();
+        let _ = writeln!(
+            out,
+            "# HELP tracker_maker_uptime_ratio Share of probes a maker answered."
+        );
+        let _ = writeln!(out, "# TYPE tracker_maker_uptime_ratio gauge");
+        for (address, stats) in &probes {
+            let address = escape_label(address);
+            let windows = [
+                ("1h", stats.uptime.hour),
+                ("24h", stats.uptime.day),
+                ("7d", stats.uptime.week),
+            ];
+            for (window, ratio) in windows {
+                if let Some(ratio) = ratio {
+                    let _ = writeln!(
+                        out,
+                        "tracker_maker_uptime_ratio{{address=\"{address}\",window=\"{window}\"}} {ratio}"
+                    );
+                }
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracker_maker_latency_ms Mean ping round-trip time over 24 hours."
+        );
+        let _ = writeln!(out, "# TYPE tracker_maker_latency_ms gauge");
+        for (address, stats) in &probes {
+            if let Some(latency) = stats.latency_ms {
+                let address = escape_label(address);
+                let _ = writeln!(
+                    out,
+                    "tracker_maker_latency_ms{{address=\"{address}\"}} {latency}"
+                );
+            }
+        }
+        let mut by_software = BTreeMap::new();
+        let mut by_protocol = BTreeMap::new();
+        let listed = servers
+            .iter()
+            .filter(|(_, info)| info.state.is_listed())
+            .filter_map(|(address, _)| versions.get(address));
+        for version in listed {
+            *by_software.entry(version.software.as_str()).or_insert(0u64) += 1;
+            for protocol in &version.protocols {
+                *by_protocol.entry(*protocol).or_insert(0u64) += 1;
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracker_maker_software_versions Listed makers by reported software version."
+        );
+        let _ = writeln!(out, "# TYPE tracker_maker_software_versions gauge");
+        for (software, count) in by_software {
+            let software = escape_label(software);
+            let _ = writeln!(
+                out,
+                "tracker_maker_software_versions{{version=\"{software}\"}} {count}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP tracker_maker_protocol_versions Listed makers supporting each protocol version."
+        );
+        let _ = writeln!(out, "# TYPE tracker_maker_protocol_versions gauge");
+        for (protocol, count) in by_protocol {
+            let _ = writeln!(
+                out,
+                "tracker_maker_protocol_versions{{protocol=\"{protocol}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+/// Escapes a label value reported by a maker.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,189 @@
+//! Bond key signatures on maker requests that change an existing
+//! registration, on the pongs answering the tracker's liveness probes and on
+//! the ones makers send on their own to report that they are alive.
+//!
+//! The signed message is the double SHA256 of a request tag followed by the
+//! tracker's address and the request's addresses (each prefixed by its length
+//! as a big-endian `u16`), the nonce and, except for pongs answering a ping,
+//! the timestamp (both little-endian `u64`). Committing to the tracker's
+//! address keeps a request from being replayed at another tracker.
+
+use bitcoincore_rpc::bitcoin::{
+    hashes::{Hash, sha256d},
+    secp256k1::{Message, Secp256k1, ecdsa::Signature},
+};
+use chrono::Utc;
+
+use crate::{error::TrackerError, types::FidelityBond};
+
+const UPDATE_ADDRESS_TAG: &[u8] = b"coinswap-tracker/update-address";
+const DEREGISTER_TAG: &[u8] = b"coinswap-tracker/deregister";
+const PONG_TAG: &[u8] = b"coinswap-tracker/pong";
+const LIVENESS_TAG: &[u8] = b"coinswap-tracker/liveness";
+
+/// How far a request timestamp may be from the tracker's clock, in seconds.
+pub(crate) const MAX_CLOCK_SKEW: u64 = 600;
+
+/// Message a maker signs to move its registration at the tracker at
+/// `tracker_address` from `old_address` to `new_address`.
+pub(crate) fn update_address_message(
+    tracker_address: &str,
+    old_address: &str,
+    new_address: &str,
+    nonce: u64,
+    timestamp: u64,
+) -> Message {
+    signed_message(
+        UPDATE_ADDRESS_TAG,
+        &[tracker_address, old_address, new_address],
+        &[nonce, timestamp],
+    )
+}
+
+/// Message a maker at `address` signs to leave the tracker at
+/// `tracker_address`.
+pub(crate) fn deregister_message(
+    tracker_address: &str,
+    address: &str,
+    nonce: u64,
+    timestamp: u64,
+) -> Message {
+    signed_message(
+        DEREGISTER_TAG,
+        &[tracker_address, address],
+        &[nonce, timestamp],
+    )
+}
+
+/// Message a maker at `maker_address` signs to answer the ping carrying
+/// `nonce` from the tracker at `tracker_address`. The nonce is fresh for
+/// every ping, so no timestamp is needed.
+pub(crate) fn pong_message(tracker_address: &str, maker_address: &str, nonce: u64) -> Message {
+    signed_message(PONG_TAG, &[tracker_address, maker_address], &[nonce])
+}
+
+/// Message a maker at `maker_address` signs to report to the tracker at
+/// `tracker_address` that it is alive, without being pinged.
+pub(crate) fn liveness_message(
+    tracker_address: &str,
+    maker_address: &str,
+    nonce: u64,
+    timestamp: u64,
+) -> Message {
+    signed_message(
+        LIVENESS_TAG,
+        &[tracker_address, maker_address],
+        &[nonce, timestamp],
+    )
+}
+
+fn signed_message(tag: &[u8], addresses: &[&str], numbers: &[u64]) -> Message {
+    let mut bytes = tag.to_vec();
+    for address in addresses {
+        bytes.extend((address.len() as u16).to_be_bytes());
+        bytes.extend(address.as_bytes());
+    }
+    for number in numbers {
+        bytes.extend(number.to_le_bytes());
+    }
+    Message::from_digest(sha256d::Hash::hash(&bytes).to_byte_array())
+}
+
+/// Checks that `signature` over `message` was made by the bond key and that
+/// the request is recent.
+pub(crate) fn verify_request(
+    bond: &FidelityBond,
+    message: &Message,
+    signature: &Signature,
+    timestamp: u64,
+) -> Result<(), TrackerError> {
+    let now = Utc::now().timestamp().max(0) as u64;
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW {
+        return Err(TrackerError::InvalidSignature(format!(
+            "timestamp {timestamp} is too far from {now}"
+        )));
+    }
+    verify_signature(bond, message, signature)
+}
+
+/// Checks that `signature` over `message` was made by the bond key.
+pub(crate) fn verify_signature(
+    bond: &FidelityBond,
+    message: &Message,
+    signature: &Signature,
+) -> Result<(), TrackerError> {
+    Secp256k1::verification_only()
+        .verify_ecdsa(message, signature, &bond.pubkey.inner)
+        .map_err(|e| TrackerError::InvalidSignature(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{self, bond, key};
+
+    #[test]
+    fn test_verify_request() {
+        let secp = Secp256k1::new();
+        let key = key(1);
+        let bond = bond(0);
+        let now = Utc::now().timestamp() as u64;
+
+        let message = update_address_message(
+            "tracker.onion:8080",
+            "old.onion:6102",
+            "new.onion:6102",
+            7,
+            now,
+        );
+        let signature = secp.sign_ecdsa(&message, &key);
+        assert!(verify_request(&bond, &message, &signature, now).is_ok());
+
+        // Another request, another tracker, another key or a stale timestamp
+        // are rejected.
+        let other = deregister_message("tracker.onion:8080", "old.onion:6102", 7, now);
+        assert!(verify_request(&bond, &other, &signature, now).is_err());
+        let other = update_address_message(
+            "other.onion:8080",
+            "old.onion:6102",
+            "new.onion:6102",
+            7,
+            now,
+        );
+        assert!(verify_request(&bond, &other, &signature, now).is_err());
+        let other_key = test_utils::key(2);
+        let forged = secp.sign_ecdsa(&message, &other_key);
+        assert!(verify_request(&bond, &message, &forged, now).is_err());
+        let stale = now - MAX_CLOCK_SKEW - 1;
+        let message = update_address_message(
+            "tracker.onion:8080",
+            "old.onion:6102",
+            "new.onion:6102",
+            7,
+            stale,
+        );
+        let signature = secp.sign_ecdsa(&message, &key);
+        assert!(verify_request(&bond, &message, &signature, stale).is_err());
+    }
+
+    #[test]
+    fn test_verify_pong() {
+        let secp = Secp256k1::new();
+        let key = key(1);
+        let bond = bond(0);
+
+        let message = pong_message("tracker.onion:8080", "maker.onion:6102", 42);
+        let signature = secp.sign_ecdsa(&message, &key);
+        assert!(verify_signature(&bond, &message, &signature).is_ok());
+
+        // The signature does not answer another ping, tracker or maker.
+        for other in [
+            pong_message("tracker.onion:8080", "maker.onion:6102", 43),
+            pong_message("other.onion:8080", "maker.onion:6102", 42),
+            pong_message("tracker.onion:8080", "other.onion:6102", 42),
+            liveness_message("tracker.onion:8080", "maker.onion:6102", 42, 0),
+        ] {
+            assert!(verify_signature(&bond, &other, &signature).is_err());
+        }
+    }
+}
@@ -1,3 +1,4 @@
+mod maker_auth;
 mod tracker_monitor;
 mod tracker_server;
 use tokio::{
@@ -5,7 +6,8 @@ use tokio::{
     net::tcp::WriteHalf,
 };
 
-pub use tracker_server::run;
+pub use tracker_monitor::{ProbeSchedule, StreamIsolation};
+pub use tracker_server::{ServerConfig, run};
 
 use crate::error::TrackerError;
 
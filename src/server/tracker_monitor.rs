@@ -1,120 +1,864 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
+use rand::{Rng, SeedableRng, rngs::StdRng};
 #[cfg(feature = "integration-test")]
 use tokio::net::TcpStream;
 use tokio::{
     io::BufWriter,
-    sync::mpsc::Sender,
-    time::{Instant, sleep},
+    net::tcp::{ReadHalf, WriteHalf},
+    sync::{
+        Semaphore,
+        broadcast::{self, error::RecvError},
+        mpsc,
+        mpsc::Sender,
+    },
+    time::{Instant, sleep_until, timeout},
 };
-use tokio_socks::tcp::Socks5Stream;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
 
+#[cfg(not(feature = "integration-test"))]
+use crate::proxy::SocksProxy;
 use crate::{
     error::TrackerError,
     handle_result,
-    server::send_message_with_prefix,
+    server::{
+        maker_auth::{pong_message, verify_signature},
+        send_message_with_prefix,
+    },
     status,
-    types::{DbRequest, ServerInfo, TrackerClientToServer, TrackerServerToClient},
+    types::{
+        DbRequest, FidelityBond, MakerVersion, Offer, RegistryEvent, ServerInfo,
+        TrackerClientToServer, TrackerServerToClient,
+    },
     utils::read_message,
 };
 
 use tokio::io::BufReader;
 
-const COOLDOWN_PERIOD: u64 = 5;
-pub async fn monitor_systems(
+/// Fraction by which every probe delay is randomly stretched or shrunk, so
+/// probes of different makers do not line up in time.
+const JITTER: f64 = 0.2;
+
+/// Bounds on a reported version, which ends up in metric labels.
+const MAX_SOFTWARE_VERSION_LEN: usize = 64;
+const MAX_PROTOCOL_VERSIONS: usize = 16;
+
+/// Wait before asking again for the registry after the DB manager did not
+/// answer.
+const RESYNC_RETRY: Duration = Duration::from_secs(1);
+
+/// When and how makers are probed.
+#[derive(Debug, Clone)]
+pub struct ProbeSchedule {
+    /// Probes in flight at once.
+    pub concurrency: usize,
+    /// Time between two probes of a maker that answers.
+    pub interval: Duration,
+    /// Longest wait between probes of a maker that keeps failing. The wait
+    /// doubles with every consecutive failure up to this bound.
+    pub max_backoff: Duration,
+    /// Time allowed to open a connection to the maker.
+    pub connect_timeout: Duration,
+    /// Time allowed for the maker to answer the ping.
+    pub response_timeout: Duration,
+    /// Which probes may share a Tor circuit.
+    pub isolation: StreamIsolation,
+}
+
+impl Default for ProbeSchedule {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            interval: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(3600),
+            // Onion circuits regularly take tens of seconds to build.
+            connect_timeout: Duration::from_secs(30),
+            response_timeout: Duration::from_secs(30),
+            isolation: StreamIsolation::default(),
+        }
+    }
+}
+
+impl ProbeSchedule {
+    /// Delay before probing a maker again after `failures` consecutive
+    /// failed probes, jittered.
+    fn delay<R: Rng>(&self, failures: u32, rng: &mut R) -> Duration {
+        let backoff = self
+            .interval
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max_backoff.max(self.interval));
+        backoff.mul_f64(rng.gen_range(1.0 - JITTER..=1.0 + JITTER))
+    }
+}
+
+/// Which probes may share a Tor circuit. Probes are told apart by their SOCKS
+/// credentials, which Tor isolates streams by (`IsolateSOCKSAuth`, on by
+/// default), so relays on a circuit cannot link probes of different makers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamIsolation {
+    /// Every probe goes over whatever circuit Tor picks.
+    None,
+    /// Each maker is probed over its own circuits.
+    #[default]
+    PerMaker,
+    /// Probes started within the same probe interval share circuits, and
+    /// every interval starts on new ones.
+    PerSweep,
+}
+
+impl FromStr for StreamIsolation {
+    type Err = TrackerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(StreamIsolation::None),
+            "per-maker" => Ok(StreamIsolation::PerMaker),
+            "per-sweep" => Ok(StreamIsolation::PerSweep),
+            _ => Err(TrackerError::General(format!(
+                "Unknown stream isolation: {s}"
+            ))),
+        }
+    }
+}
+
+#[cfg(not(feature = "integration-test"))]
+impl StreamIsolation {
+    /// SOCKS username and password for probing `address` during `sweep`.
+    /// `session` is drawn once per run, so circuits are not reused across
+    /// restarts.
+    fn credentials(self, address: &str, sweep: u64, session: &str) -> Option<(String, String)> {
+        match self {
+            StreamIsolation::None => None,
+            StreamIsolation::PerMaker => Some((address.to_string(), session.to_string())),
+            StreamIsolation::PerSweep => Some((format!("sweep-{sweep}"), session.to_string())),
+        }
+    }
+}
+
+/// Where probes are sent through and which tracker they come from.
+#[derive(Debug, Clone)]
+pub(crate) struct ProbeOrigin {
+    #[cfg(not(feature = "integration-test"))]
+    pub(crate) proxy: SocksProxy,
+    /// Hostname of the tracker, as given in pings.
+    pub(crate) onion_address: String,
+    pub(crate) port: u16,
+}
+
+impl ProbeOrigin {
+    /// Address of the tracker that makers sign their pongs for.
+    fn tracker_address(&self) -> String {
+        format!("{}:{}", self.onion_address, self.port)
+    }
+}
+
+#[derive(Debug)]
+struct ProbeState {
+    next_probe: Instant,
+    failures: u32,
+    in_flight: bool,
+    /// Bond the maker's pong must be signed with.
+    bond: Option<FidelityBond>,
+}
+
+#[derive(Debug)]
+struct ProbeOutcome {
+    /// Address that was probed.
+    probed: String,
+    /// What the maker answered, or why the probe failed.
+    result: Result<ProbeAnswer, TrackerError>,
+}
+
+/// What a maker that passed a probe told the tracker.
+#[derive(Debug)]
+struct ProbeAnswer {
+    /// Round-trip time of the ping.
+    rtt: Duration,
+    /// Unset for makers that predate offers.
+    offer: Option<Offer>,
+    /// Unset for makers that predate version reports.
+    version: Option<MakerVersion>,
+}
+
+pub(crate) async fn monitor_systems(
     db_tx: Sender<DbRequest>,
     status_tx: status::Sender,
-    #[cfg(not(feature = "integration-test"))] socks_port: u16,
-    onion_address: String,
-    port: u16,
+    origin: ProbeOrigin,
+    schedule: ProbeSchedule,
+    legacy_announcements: bool,
+    mut events: broadcast::Receiver<RegistryEvent>,
 ) -> Result<(), TrackerError> {
-    info!("Starting to monitor other maker services");
+    info!("Starting to monitor other maker services with {schedule:?}");
+
+    let semaphore = Arc::new(Semaphore::new(schedule.concurrency.max(1)));
+    let (outcome_tx, mut outcome_rx) = mpsc::channel::<ProbeOutcome>(schedule.concurrency.max(1));
+    let mut makers: HashMap<String, ProbeState> = HashMap::new();
+    let mut rng = StdRng::from_entropy();
+    #[cfg(not(feature = "integration-test"))]
+    let (session, started) = (format!("{:016x}", rng.r#gen::<u64>()), Instant::now());
+    // The registry is read in full at startup and after missing events;
+    // otherwise the events keep `makers` current.
+    let mut resync = true;
 
     loop {
-        let (response_tx, mut response_rx) = tokio::sync::mpsc::channel(1);
-        if db_tx.send(DbRequest::QueryAll(response_tx)).await.is_err() {
-            continue;
+        if resync {
+            let (response_tx, mut response_rx) = mpsc::channel(1);
+            handle_result!(
+                status_tx,
+                db_tx.send(DbRequest::QueryAll(response_tx)).await
+            );
+            let Some(response) = response_rx.recv().await else {
+                tokio::time::sleep(RESYNC_RETRY).await;
+                continue;
+            };
+            resync = false;
+
+            // Makers loaded at once are spread over a fraction of the interval.
+            let now = Instant::now();
+            let known: HashSet<&String> = response.iter().map(|(address, _)| address).collect();
+            makers.retain(|address, _| known.contains(address));
+            for (address, info) in response {
+                let first_probe = now + schedule.interval.mul_f64(rng.gen_range(0.0..JITTER));
+                track(&mut makers, address, info, first_probe);
+            }
+            info!("Monitoring {} makers", makers.len());
         }
 
-        if let Some(response) = response_rx.recv().await {
-            for (address, server_info) in response {
-                let cooldown_duration = Duration::from_secs(COOLDOWN_PERIOD);
-                if server_info.cooldown.elapsed() <= cooldown_duration {
-                    continue;
+        let wake = makers
+            .values()
+            .filter(|state| !state.in_flight)
+            .map(|state| state.next_probe)
+            .min()
+            .unwrap_or_else(|| Instant::now() + schedule.interval);
+
+        tokio::select! {
+            event = events.recv() => match event {
+                // Newly registered makers are probed right away.
+                Ok(event) => apply_event(&mut makers, event, Instant::now()),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Missed {missed} registry events, reloading the registry");
+                    resync = true;
                 }
-                info!("Address to query: {:?}", address);
+                Err(RecvError::Closed) => break,
+            },
+            _ = sleep_until(wake) => {
+                let now = Instant::now();
+                for (address, state) in makers.iter_mut() {
+                    if state.in_flight || state.next_probe > now {
+                        continue;
+                    }
+                    state.in_flight = true;
+                    info!("Address to query: {:?}", address);
 
-                let mut success = false;
-                for attempt in 1..=3 {
+                    let semaphore = semaphore.clone();
+                    let outcome_tx = outcome_tx.clone();
+                    let probed = address.clone();
+                    let bond = state.bond.clone();
+                    let nonce = rng.r#gen();
                     #[cfg(not(feature = "integration-test"))]
-                    let connect_result = Socks5Stream::connect(
-                        format!("127.0.0.1:{socks_port:?}").as_str(),
-                        address.clone(),
-                    )
-                    .await;
-
-                    #[cfg(feature = "integration-test")]
-                    let connect_result = TcpStream::connect(address.clone()).await;
-
-                    match connect_result {
-                        Ok(mut stream) => {
-                            success = true;
-
-                            let (read_half, write_half) = stream.split();
-
-                            let mut reader = BufReader::new(read_half);
-
-                            let mut writer = BufWriter::new(write_half);
-
-                            let message = TrackerServerToClient::Ping {
-                                address: onion_address.clone(),
-                                port,
-                            };
-                            _ = send_message_with_prefix(&mut writer, &message).await;
-
-                            let buffer = handle_result!(status_tx, read_message(&mut reader).await);
-                            let response: TrackerClientToServer =
-                                match serde_cbor::de::from_reader(&buffer[..]) {
-                                    Ok(resp) => resp,
-                                    Err(e) => {
-                                        error!("Deserialization error: {e:?}");
-                                        sleep(Duration::from_secs(1)).await;
-                                        continue;
-                                    }
-                                };
-
-                            if let TrackerClientToServer::Pong { address } = response {
-                                let updated_info = ServerInfo {
-                                    onion_address: address.clone(),
-                                    cooldown: Instant::now(),
-                                    stale: false,
-                                };
-                                let _ = db_tx.send(DbRequest::Update(address, updated_info)).await;
-                            }
-
-                            break;
+                    let credentials = {
+                        let sweep = started.elapsed().as_secs() / schedule.interval.as_secs().max(1);
+                        schedule.isolation.credentials(address, sweep, &session)
+                    };
+                    let origin = origin.clone();
+                    let schedule = schedule.clone();
+                    tokio::spawn(async move {
+                        let Ok(_permit) = semaphore.acquire_owned().await else {
+                            return;
+                        };
+                        let result = probe(
+                            &probed,
+                            bond.as_ref(),
+                            legacy_announcements,
+                            nonce,
+                            #[cfg(not(feature = "integration-test"))]
+                            credentials,
+                            &origin,
+                            &schedule,
+                        )
+                        .await;
+                        let _ = outcome_tx.send(ProbeOutcome { probed, result }).await;
+                    });
+                }
+            }
+            Some(outcome) = outcome_rx.recv() => {
+                let ProbeOutcome { probed, result } = outcome;
+                let (mut offer, mut version) = (None, None);
+                let outcome = match result {
+                    Ok(answer) => {
+                        if let Some(state) = makers.get_mut(&probed) {
+                            state.failures = 0;
                         }
-
-                        Err(e) => {
-                            warn!(
-                                "Failed to connect to {} (attempt {}/3): {}",
-                                address, attempt, e
-                            );
-                            sleep(Duration::from_secs(1)).await;
+                        offer = answer.offer;
+                        version = answer.version;
+                        Ok(answer.rtt)
+                    }
+                    Err(e) => {
+                        warn!("Failed to probe {probed}: {e}");
+                        if let Some(state) = makers.get_mut(&probed) {
+                            state.failures = state.failures.saturating_add(1);
                         }
+                        Err(error_kind(&e))
                     }
+                };
+                if let Some(state) = makers.get_mut(&probed) {
+                    state.in_flight = false;
+                    state.next_probe = Instant::now() + schedule.delay(state.failures, &mut rng);
+                }
+                let db_request = DbRequest::RecordProbe { address: probed.clone(), outcome };
+                handle_result!(status_tx, db_tx.send(db_request).await);
+                if let Some(offer) = offer {
+                    let db_request = DbRequest::StoreOffer { address: probed.clone(), offer };
+                    handle_result!(status_tx, db_tx.send(db_request).await);
+                }
+                if let Some(version) = version {
+                    let db_request = DbRequest::StoreVersion { address: probed, version };
+                    handle_result!(status_tx, db_tx.send(db_request).await);
                 }
+            }
+        }
+    }
 
-                if !success && !server_info.stale {
-                    let updated_info = ServerInfo {
-                        stale: true,
-                        ..server_info
-                    };
-                    let _ = db_tx.send(DbRequest::Update(address, updated_info)).await;
+    Ok(())
+}
+
+/// Applies a registry change to the probed makers. Makers added or updated
+/// into a probed state are first probed at `first_probe`.
+fn apply_event(
+    makers: &mut HashMap<String, ProbeState>,
+    event: RegistryEvent,
+    first_probe: Instant,
+) {
+    match event {
+        RegistryEvent::Added(address, info) | RegistryEvent::Updated(address, info) => {
+            track(makers, address, info, first_probe)
+        }
+        RegistryEvent::Removed(address) => {
+            makers.remove(&address);
+        }
+    }
+}
+
+/// Starts or keeps probing `address` while its state is probed, with the
+/// bond its pongs must be signed with. Makers leaving the probed states are
+/// dropped.
+fn track(
+    makers: &mut HashMap<String, ProbeState>,
+    address: String,
+    info: ServerInfo,
+    first_probe: Instant,
+) {
+    if !info.state.is_probed() {
+        makers.remove(&address);
+        return;
+    }
+    makers
+        .entry(address)
+        .or_insert_with(|| ProbeState {
+            next_probe: first_probe,
+            failures: 0,
+            in_flight: false,
+            bond: None,
+        })
+        .bond = info.bond;
+}
+
+/// Pings the maker at `address` with `nonce` and returns the time from ping
+/// to pong, along with the maker's offer and versions. The pong must be
+/// signed by the key of `bond`. A maker without a bond can only have been
+/// registered by a legacy announcement: its pong is taken on trust while
+/// `legacy` is set and fails the probe otherwise, so such makers are evicted
+/// once legacy announcements are turned off. A maker that does not send an
+/// offer or its versions still passes the probe.
+async fn probe(
+    address: &str,
+    bond: Option<&FidelityBond>,
+    legacy: bool,
+    nonce: u64,
+    #[cfg(not(feature = "integration-test"))] credentials: Option<(String, String)>,
+    origin: &ProbeOrigin,
+    schedule: &ProbeSchedule,
+) -> Result<ProbeAnswer, TrackerError> {
+    if bond.is_none() && !legacy {
+        return Err(TrackerError::InvalidSignature(
+            "no bond to verify the pong against".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "integration-test"))]
+    let connect = origin.proxy.connect(address, credentials);
+
+    #[cfg(feature = "integration-test")]
+    let connect = async { Ok::<_, TrackerError>(TcpStream::connect(address).await?) };
+
+    let mut stream = timeout(schedule.connect_timeout, connect)
+        .await
+        .map_err(|_| timed_out("connect"))??;
+
+    let (read_half, write_half) = stream.split();
+    let mut reader = BufReader::new(read_half);
+    let mut writer = BufWriter::new(write_half);
+
+    let message = TrackerServerToClient::Ping {
+        address: origin.onion_address.clone(),
+        port: origin.port,
+        nonce,
+    };
+    let sent_at = Instant::now();
+    send_message_with_prefix(&mut writer, &message).await?;
+
+    let buffer = timeout(schedule.response_timeout, read_message(&mut reader))
+        .await
+        .map_err(|_| timed_out("pong"))??;
+    let rtt = sent_at.elapsed();
+    let version = match serde_cbor::de::from_reader(&buffer[..])? {
+        // Only the probed address counts, whatever address the pong names.
+        TrackerClientToServer::Pong {
+            signature, version, ..
+        } => {
+            if let Some(bond) = bond {
+                let signature = signature
+                    .ok_or_else(|| TrackerError::InvalidSignature("unsigned pong".to_string()))?;
+                let message = pong_message(&origin.tracker_address(), address, nonce);
+                verify_signature(bond, &message, &signature)?;
+            }
+            version.filter(|version| {
+                let plausible = version.software.len() <= MAX_SOFTWARE_VERSION_LEN
+                    && version.protocols.len() <= MAX_PROTOCOL_VERSIONS;
+                if !plausible {
+                    info!("Ignoring oversized version report from {address}");
                 }
+                plausible
+            })
+        }
+        other => {
+            return Err(TrackerError::General(format!(
+                "Unexpected reply to ping: {other:?}"
+            )));
+        }
+    };
+
+    let offer = match fetch_offer(&mut reader, &mut writer, schedule).await {
+        Ok(offer) => Some(offer),
+        Err(e) => {
+            info!("No offer from {address}: {e}");
+            None
+        }
+    };
+    Ok(ProbeAnswer {
+        rtt,
+        offer,
+        version,
+    })
+}
+
+/// Asks a maker that answered a ping for its offer.
+async fn fetch_offer(
+    reader: &mut BufReader<ReadHalf<'_>>,
+    writer: &mut BufWriter<WriteHalf<'_>>,
+    schedule: &ProbeSchedule,
+) -> Result<Offer, TrackerError> {
+    send_message_with_prefix(writer, &TrackerServerToClient::GetOffer).await?;
+    let buffer = timeout(schedule.response_timeout, read_message(reader))
+        .await
+        .map_err(|_| timed_out("offer"))??;
+    match serde_cbor::de::from_reader(&buffer[..])? {
+        TrackerClientToServer::Offer { offer } => Ok(offer),
+        other => Err(TrackerError::General(format!(
+            "Unexpected reply to offer request: {other:?}"
+        ))),
+    }
+}
+
+/// Kind of error a probe failed with, as stored in the probe history.
+fn error_kind(e: &TrackerError) -> String {
+    match e.io_error_kind() {
+        Some(kind) => format!("{kind:?}"),
+        None => e.kind().to_string(),
+    }
+}
+
+fn timed_out(what: &str) -> TrackerError {
+    std::io::Error::new(ErrorKind::TimedOut, format!("timed out waiting for {what}")).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MakerState;
+
+    #[test]
+    fn test_delay_backs_off_with_jitter() {
+        let schedule = ProbeSchedule {
+            interval: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+        let within = |delay: Duration, base: u64| {
+            let base = Duration::from_secs(base);
+            delay >= base.mul_f64(1.0 - JITTER) && delay <= base.mul_f64(1.0 + JITTER)
+        };
+
+        for _ in 0..100 {
+            assert!(within(schedule.delay(0, &mut rng), 60));
+            assert!(within(schedule.delay(1, &mut rng), 120));
+            assert!(within(schedule.delay(3, &mut rng), 480));
+            // Capped, including for counts that would overflow.
+            assert!(within(schedule.delay(4, &mut rng), 600));
+            assert!(within(schedule.delay(u32::MAX, &mut rng), 600));
+        }
+
+        // Delays are spread out rather than all equal.
+        let delays: Vec<_> = (0..10).map(|_| schedule.delay(0, &mut rng)).collect();
+        assert!(delays.iter().any(|d| *d != delays[0]));
+    }
+
+    #[cfg(not(feature = "integration-test"))]
+    #[test]
+    fn test_isolation_credentials() {
+        let per_maker = |address, sweep| StreamIsolation::PerMaker.credentials(address, sweep, "s");
+        let per_sweep = |address, sweep| StreamIsolation::PerSweep.credentials(address, sweep, "s");
+
+        assert_eq!(StreamIsolation::None.credentials("a:1", 0, "s"), None);
+        assert_ne!(per_maker("a:1", 0), per_maker("b:1", 0));
+        assert_eq!(per_maker("a:1", 0), per_maker("a:1", 1));
+        assert_eq!(per_sweep("a:1", 0), per_sweep("b:1", 0));
+        assert_ne!(per_sweep("a:1", 0), per_sweep("a:1", 1));
+        // Another run never shares circuits with this one.
+        assert_ne!(
+            StreamIsolation::PerMaker.credentials("a:1", 0, "t"),
+            per_maker("a:1", 0)
+        );
+    }
+
+    #[cfg(feature = "integration-test")]
+    use crate::test_utils::{self, key, offer, version};
+    use crate::test_utils::{bond, server};
+    #[cfg(feature = "integration-test")]
+    use crate::utils::send_message;
+    #[cfg(feature = "integration-test")]
+    use bitcoincore_rpc::bitcoin::secp256k1::{Secp256k1, SecretKey};
+    #[cfg(feature = "integration-test")]
+    use tokio::net::TcpListener;
+
+    fn info(address: &str, state: MakerState, bond: Option<FidelityBond>) -> ServerInfo {
+        ServerInfo {
+            state,
+            ..server(address, bond)
+        }
+    }
+
+    /// A maker answering one ping for the address it is reached at, or, when
+    /// `vouch_for` is set, signing for that address instead. Without an
+    /// `offer` it sends no version and hangs up after the pong, like makers
+    /// predating both.
+    #[cfg(feature = "integration-test")]
+    async fn maker(
+        key: SecretKey,
+        vouch_for: Option<&'static str>,
+        offer: Option<Offer>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let own = address.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (read_half, write_half) = stream.split();
+            let mut reader = BufReader::new(read_half);
+            let mut writer = BufWriter::new(write_half);
+            let buffer = read_message(&mut reader).await.unwrap();
+            let TrackerServerToClient::Ping {
+                address,
+                port,
+                nonce,
+            } = serde_cbor::de::from_reader(&buffer[1..]).unwrap()
+            else {
+                panic!("expected a ping");
+            };
+            let signed_for = vouch_for.map_or(own.clone(), str::to_string);
+            let message = pong_message(&format!("{address}:{port}"), &signed_for, nonce);
+            let pong = TrackerClientToServer::Pong {
+                address: signed_for,
+                signature: Some(Secp256k1::new().sign_ecdsa(&message, &key)),
+                nonce: None,
+                timestamp: None,
+                version: offer.is_some().then(|| version("coinswap/0.2.0", &[1, 2])),
+            };
+            send_message(&mut writer, &pong).await.unwrap();
+            let Some(offer) = offer else {
+                return;
+            };
+            let buffer = read_message(&mut reader).await.unwrap();
+            let TrackerServerToClient::GetOffer =
+                serde_cbor::de::from_reader(&buffer[1..]).unwrap()
+            else {
+                panic!("expected an offer request");
+            };
+            let reply = TrackerClientToServer::Offer { offer };
+            send_message(&mut writer, &reply).await.unwrap();
+        });
+        address
+    }
+
+    /// A maker predating signed pongs, answering one ping with nothing but
+    /// its address.
+    #[cfg(feature = "integration-test")]
+    async fn legacy_maker() -> String {
+        #[derive(serde::Serialize)]
+        enum LegacyReply {
+            Pong { address: String },
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let own = address.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (read_half, write_half) = stream.split();
+            let mut reader = BufReader::new(read_half);
+            let mut writer = BufWriter::new(write_half);
+            read_message(&mut reader).await.unwrap();
+            let pong = LegacyReply::Pong { address: own };
+            send_message(&mut writer, &pong).await.unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn test_registry_events_update_probed_makers() {
+        let mut makers = HashMap::new();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+
+        let added = info("a:1", MakerState::Pending, Some(bond(0)));
+        apply_event(&mut makers, RegistryEvent::Added("a:1".into(), added), now);
+        assert_eq!(makers["a:1"].next_probe, now);
+
+        // Updates keep the schedule and pick up a new bond.
+        makers.get_mut("a:1").unwrap().failures = 3;
+        let mut moved = bond(0);
+        moved.outpoint.vout = 1;
+        let updated = info("a:1", MakerState::Degraded, Some(moved.clone()));
+        apply_event(
+            &mut makers,
+            RegistryEvent::Updated("a:1".into(), updated),
+            later,
+        );
+        assert_eq!(makers["a:1"].next_probe, now);
+        assert_eq!(makers["a:1"].failures, 3);
+        assert_eq!(makers["a:1"].bond, Some(moved));
+
+        // Makers leaving the probed states or the registry are dropped.
+        let banned = info("a:1", MakerState::Banned, Some(bond(0)));
+        apply_event(
+            &mut makers,
+            RegistryEvent::Updated("a:1".into(), banned),
+            now,
+        );
+        assert!(makers.is_empty());
+        let evicted = info("b:1", MakerState::Evicted, None);
+        apply_event(
+            &mut makers,
+            RegistryEvent::Added("b:1".into(), evicted),
+            now,
+        );
+        assert!(makers.is_empty());
+        let added = info("c:1", MakerState::Active, None);
+        apply_event(&mut makers, RegistryEvent::Added("c:1".into(), added), now);
+        apply_event(&mut makers, RegistryEvent::Removed("c:1".into()), now);
+        assert!(makers.is_empty());
+    }
+
+    #[cfg(feature = "integration-test")]
+    fn origin() -> ProbeOrigin {
+        ProbeOrigin {
+            onion_address: "tracker.onion".to_string(),
+            port: 8080,
+        }
+    }
+
+    /// Runs the monitor against a registry channel, answering its first
+    /// snapshot with `registry`.
+    #[cfg(feature = "integration-test")]
+    async fn start_monitor(
+        schedule: ProbeSchedule,
+        events: broadcast::Receiver<RegistryEvent>,
+        registry: Vec<(String, ServerInfo)>,
+    ) -> mpsc::Receiver<DbRequest> {
+        let (db_tx, mut db_rx) = mpsc::channel(16);
+        let (status_tx, _) = mpsc::channel(16);
+        tokio::spawn(monitor_systems(
+            db_tx,
+            status::Sender::Server(status_tx),
+            origin(),
+            schedule,
+            false,
+            events,
+        ));
+        let Some(DbRequest::QueryAll(resp_tx)) = db_rx.recv().await else {
+            panic!("expected a registry snapshot");
+        };
+        resp_tx.send(registry).await.unwrap();
+        db_rx
+    }
+
+    /// Waits for the monitor to record a probe, answering snapshots with
+    /// `registry`.
+    #[cfg(feature = "integration-test")]
+    async fn next_probe(
+        db_rx: &mut mpsc::Receiver<DbRequest>,
+        registry: &[(String, ServerInfo)],
+    ) -> (String, Result<Duration, String>) {
+        loop {
+            match timeout(Duration::from_secs(5), db_rx.recv()).await.unwrap() {
+                Some(DbRequest::RecordProbe { address, outcome }) => return (address, outcome),
+                Some(DbRequest::QueryAll(resp_tx)) => {
+                    resp_tx.send(registry.to_vec()).await.unwrap()
+                }
+                Some(_) => panic!("unexpected request"),
+                None => panic!("monitor stopped"),
             }
-            sleep(Duration::from_secs(4)).await;
         }
     }
+
+    #[cfg(feature = "integration-test")]
+    #[tokio::test]
+    async fn test_new_makers_are_probed_promptly() {
+        let key = key(1);
+        let (events_tx, events_rx) = broadcast::channel(16);
+        // Far longer than the test may take.
+        let schedule = ProbeSchedule {
+            interval: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let mut db_rx = start_monitor(schedule, events_rx, Vec::new()).await;
+
+        let address = maker(key, None, None).await;
+        let registered = Instant::now();
+        let added = info(&address, MakerState::Pending, Some(bond(0)));
+        events_tx
+            .send(RegistryEvent::Added(address.clone(), added))
+            .unwrap();
+        let (probed, outcome) = next_probe(&mut db_rx, &[]).await;
+        assert_eq!(probed, address);
+        assert!(outcome.is_ok());
+        assert!(registered.elapsed() < Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "integration-test")]
+    #[tokio::test]
+    async fn test_missed_events_reload_the_registry() {
+        let key = key(1);
+        let (events_tx, events_rx) = broadcast::channel(1);
+        let schedule = ProbeSchedule {
+            interval: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let mut db_rx = start_monitor(schedule, events_rx, Vec::new()).await;
+
+        // Both events are sent before the monitor runs, so it misses the
+        // first and has to reload the registry to learn about `a`.
+        let mut registry = Vec::new();
+        for _ in 0..2 {
+            let address = maker(key, None, None).await;
+            let added = info(&address, MakerState::Pending, Some(bond(0)));
+            registry.push((address, added));
+        }
+        for (address, info) in &registry {
+            let event = RegistryEvent::Added(address.clone(), info.clone());
+            events_tx.send(event).unwrap();
+        }
+
+        let mut probed = Vec::new();
+        while probed.len() < 2 {
+            let (address, outcome) = next_probe(&mut db_rx, &registry).await;
+            assert!(outcome.is_ok());
+            if !probed.contains(&address) {
+                probed.push(address);
+            }
+        }
+        probed.sort();
+        let mut expected: Vec<_> = registry.into_iter().map(|(address, _)| address).collect();
+        expected.sort();
+        assert_eq!(probed, expected);
+    }
+
+    #[cfg(feature = "integration-test")]
+    #[tokio::test]
+    async fn test_probe_verifies_pong() {
+        let key = key(1);
+        let bond = bond(0);
+        let offer = offer(1_000, 10_000, 1_000_000);
+
+        let schedule = ProbeSchedule::default();
+        let probe = |address: String, bond: Option<FidelityBond>| {
+            let schedule = schedule.clone();
+            async move { probe(&address, bond.as_ref(), false, 7, &origin(), &schedule).await }
+        };
+
+        let address = maker(key, None, Some(offer.clone())).await;
+        let answer = probe(address, Some(bond.clone())).await.unwrap();
+        assert_eq!(answer.offer, Some(offer));
+        assert_eq!(answer.version, Some(version("coinswap/0.2.0", &[1, 2])));
+
+        // A maker without offers or versions still answers the probe.
+        let address = maker(key, None, None).await;
+        let answer = probe(address, Some(bond.clone())).await.unwrap();
+        assert_eq!(answer.offer, None);
+        assert_eq!(answer.version, None);
+
+        // A host vouching for another maker's address is not believed.
+        let address = maker(key, Some("other.onion:6102"), None).await;
+        let e = probe(address, Some(bond.clone())).await.unwrap_err();
+        assert_eq!(e.kind(), "InvalidSignature");
+
+        // Nor is a pong signed by another key, or one for a maker without a
+        // bond.
+        let other_key = test_utils::key(2);
+        let address = maker(other_key, None, None).await;
+        assert!(probe(address, Some(bond)).await.is_err());
+        let address = maker(key, None, None).await;
+        assert!(probe(address, None).await.is_err());
+    }
+
+    #[cfg(feature = "integration-test")]
+    #[tokio::test]
+    async fn test_probe_trusts_legacy_makers() {
+        let schedule = ProbeSchedule::default();
+
+        // Makers registered by a legacy announcement have no bond to sign
+        // with, so any pong passes while legacy announcements are accepted,
+        // including one without a signature.
+        let address = maker(test_utils::key(3), None, None).await;
+        let answer = probe(&address, None, true, 7, &origin(), &schedule).await;
+        assert!(answer.is_ok());
+        let address = legacy_maker().await;
+        let answer = probe(&address, None, true, 7, &origin(), &schedule).await;
+        assert!(answer.is_ok());
+        let address = legacy_maker().await;
+        assert!(
+            probe(&address, None, false, 7, &origin(), &schedule)
+                .await
+                .is_err()
+        );
+
+        // A maker with a bond still has to sign for it.
+        let address = maker(test_utils::key(3), None, None).await;
+        let e = probe(&address, Some(&bond(0)), true, 7, &origin(), &schedule)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), "InvalidSignature");
+        let address = legacy_maker().await;
+        let e = probe(&address, Some(&bond(0)), true, 7, &origin(), &schedule)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), "InvalidSignature");
+    }
 }
@@ -1,53 +1,130 @@
+use std::sync::Arc;
+
 use crate::db::model::MempoolTx;
-use crate::server::tracker_monitor::monitor_systems;
+use crate::error::TrackerError;
+use crate::indexer::rpc::BitcoinRpc;
+use crate::indexer::{is_valid_maker_address, verify_proof};
+#[cfg(not(feature = "integration-test"))]
+use crate::proxy::SocksProxy;
+use crate::server::maker_auth::{
+    deregister_message, liveness_message, update_address_message, verify_request,
+};
+use crate::server::tracker_monitor::{ProbeOrigin, ProbeSchedule, monitor_systems};
 use crate::status;
 use crate::types::DbRequest;
+use crate::types::FidelityBond;
+use crate::types::FidelityProof;
+use crate::types::MakerState;
+use crate::types::RegistryEvent;
+use crate::types::ServerInfo;
 use crate::types::TrackerClientToServer;
 use crate::types::TrackerServerToClient;
 use crate::utils::read_message;
 use crate::utils::send_message;
+use chrono::Utc;
+use diesel::SqliteConnection;
+use diesel::r2d2::ConnectionManager;
+use r2d2::Pool;
 use tokio::io::BufReader;
 use tokio::io::BufWriter;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
+
+/// Settings of the tracker server and of the monitor probing makers.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the server listens on.
+    pub address: String,
+    /// Hostname makers reach the tracker at.
+    pub onion_address: String,
+    #[cfg(not(feature = "integration-test"))]
+    pub proxy: SocksProxy,
+    pub min_bond_confirmations: u32,
+    pub probe_schedule: ProbeSchedule,
+    /// Whether legacy announcements are accepted, in which case the makers
+    /// they register without a bond are probed without checking the
+    /// signature of their pongs.
+    pub legacy_announcements: bool,
+}
 
 pub async fn run(
+    pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
     db_tx: Sender<DbRequest>,
     status_tx: status::Sender,
-    address: String,
-    #[cfg(not(feature = "integration-test"))] socks_port: u16,
-    onion_address: String,
+    rpc: BitcoinRpc,
+    config: ServerConfig,
+    events_tx: broadcast::Sender<RegistryEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let ServerConfig {
+        address,
+        onion_address,
+        #[cfg(not(feature = "integration-test"))]
+        proxy,
+        min_bond_confirmations,
+        probe_schedule,
+        legacy_announcements,
+    } = config;
     let port = address
         .rsplit_once(':')
         .and_then(|(_, port)| port.parse::<u16>().ok())
         .unwrap_or(8080);
     let server = TcpListener::bind(&address).await?;
+    let tracker_address = Arc::new(format!("{onion_address}:{port}"));
 
-    tokio::spawn(monitor_systems(
-        db_tx.clone(),
-        status_tx.clone(),
+    let origin = ProbeOrigin {
         #[cfg(not(feature = "integration-test"))]
-        socks_port,
+        proxy,
         onion_address,
         port,
+    };
+    tokio::spawn(monitor_systems(
+        db_tx.clone(),
+        status_tx.clone(),
+        origin,
+        probe_schedule,
+        legacy_announcements,
+        events_tx.subscribe(),
     ));
 
     info!("Tracker server listening on {}", address);
 
+    let rpc = Arc::new(rpc);
     while let Ok((stream, client_addr)) = server.accept().await {
         info!("Accepted connection from {}", client_addr);
         let db_tx_clone = db_tx.clone();
-        tokio::spawn(async move { handle_client(stream, db_tx_clone).await });
+        let pool = pool.clone();
+        let rpc = rpc.clone();
+        let tracker_address = tracker_address.clone();
+        tokio::spawn(async move {
+            handle_client(
+                stream,
+                db_tx_clone,
+                pool,
+                rpc,
+                min_bond_confirmations,
+                tracker_address,
+            )
+            .await
+        });
     }
 
     Ok(())
 }
-async fn handle_client(mut stream: TcpStream, db_tx: Sender<DbRequest>) {
+async fn handle_client(
+    mut stream: TcpStream,
+    db_tx: Sender<DbRequest>,
+    pool: Arc<Pool<ConnectionManager<SqliteConnection>>>,
+    rpc: Arc<BitcoinRpc>,
+    min_bond_confirmations: u32,
+    tracker_address: Arc<String>,
+) {
     let (read_half, write_half) = stream.split();
     let mut reader = BufReader::new(read_half);
     let mut writer = BufWriter::new(write_half);
@@ -77,7 +154,7 @@ async fn handle_client(mut stream: TcpStream, db_tx: Sender<DbRequest>) {
             TrackerClientToServer::Get => {
                 info!("Received Get request taker");
                 let (resp_tx, mut resp_rx) = mpsc::channel(1);
-                let db_request = DbRequest::QueryActive(resp_tx);
+                let db_request = DbRequest::QueryActive(None, resp_tx);
 
                 if let Err(e) = db_tx.send(db_request).await {
                     error!("Failed to send DB request: {e}");
@@ -96,12 +173,167 @@ async fn handle_client(mut stream: TcpStream, db_tx: Sender<DbRequest>) {
                 }
             }
 
-            TrackerClientToServer::Post { metadata: _ } => {
-                todo!()
+            TrackerClientToServer::GetRecords => {
+                info!("Received GetRecords request from taker");
+                let (resp_tx, mut resp_rx) = mpsc::channel(1);
+
+                if let Err(e) = db_tx.send(DbRequest::QueryRecords(resp_tx)).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+
+                if let Some(records) = resp_rx.recv().await {
+                    let message = TrackerServerToClient::Records { records };
+                    if let Err(e) = send_message(&mut writer, &message).await {
+                        error!("Failed to send response to client: {e}");
+                        break;
+                    }
+                }
+            }
+
+            TrackerClientToServer::GetCompatible { protocol_version } => {
+                info!("Received GetCompatible request from taker: {protocol_version}");
+                let (resp_tx, mut resp_rx) = mpsc::channel(1);
+                let db_request = DbRequest::QueryActive(Some(protocol_version), resp_tx);
+
+                if let Err(e) = db_tx.send(db_request).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+
+                if let Some(addresses) = resp_rx.recv().await {
+                    let message = TrackerServerToClient::Address { addresses };
+                    if let Err(e) = send_message(&mut writer, &message).await {
+                        error!("Failed to send response to client: {e}");
+                        break;
+                    }
+                }
+            }
+
+            TrackerClientToServer::GetSample {
+                count,
+                min_bond,
+                seed,
+            } => {
+                info!("Received GetSample request from taker: count: {count}");
+                let (resp_tx, mut resp_rx) = mpsc::channel(1);
+                let db_request = DbRequest::QuerySample {
+                    count,
+                    min_bond,
+                    seed,
+                    resp_tx,
+                };
+
+                if let Err(e) = db_tx.send(db_request).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+
+                if let Some(addresses) = resp_rx.recv().await {
+                    let message = TrackerServerToClient::Address { addresses };
+                    if let Err(e) = send_message(&mut writer, &message).await {
+                        error!("Failed to send response to client: {e}");
+                        break;
+                    }
+                }
+            }
+
+            TrackerClientToServer::GetOffers { filter } => {
+                info!("Received GetOffers request from taker: {filter:?}");
+                let (resp_tx, mut resp_rx) = mpsc::channel(1);
+
+                if let Err(e) = db_tx.send(DbRequest::QueryOffers(filter, resp_tx)).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+
+                if let Some(offers) = resp_rx.recv().await {
+                    let message = TrackerServerToClient::Offers { offers };
+                    if let Err(e) = send_message(&mut writer, &message).await {
+                        error!("Failed to send response to client: {e}");
+                        break;
+                    }
+                }
+            }
+
+            TrackerClientToServer::Post { metadata } => {
+                info!("Received Post request from maker: {}", metadata.url);
+                if !is_valid_maker_address(&metadata.url) {
+                    warn!("Rejected maker with malformed address: {}", metadata.url);
+                    continue;
+                }
+
+                let verified = verify_proof_blocking(
+                    &pool,
+                    &rpc,
+                    metadata.proof.clone(),
+                    metadata.url.clone(),
+                    min_bond_confirmations,
+                )
+                .await
+                .and_then(|bond| Ok((bond, metadata.proof.certificate()?)));
+                let (bond, certificate) = match verified {
+                    Ok(verified) => verified,
+                    Err(e) => {
+                        warn!("Rejected maker {}: {e}", metadata.url);
+                        continue;
+                    }
+                };
+
+                let server_info = ServerInfo {
+                    onion_address: metadata.url.clone(),
+                    cooldown: Instant::now(),
+                    failures: 0,
+                    up_since: None,
+                    first_seen: Utc::now(),
+                    last_seen: None,
+                    bond: Some(bond),
+                    certificate: Some(certificate),
+                    state: MakerState::Pending,
+                };
+                if let Err(e) = db_tx.send(DbRequest::Add(metadata.url, server_info)).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
             }
 
-            TrackerClientToServer::Pong { address: _ } => {
-                todo!()
+            TrackerClientToServer::Pong {
+                address,
+                signature,
+                nonce,
+                timestamp,
+                ..
+            } => {
+                info!("Received liveness report from maker: {address}");
+                let (Some(signature), Some(nonce), Some(timestamp)) = (signature, nonce, timestamp)
+                else {
+                    warn!(
+                        "Rejected liveness report of {address} without signature, nonce or timestamp"
+                    );
+                    continue;
+                };
+                let Some(bond) = registered_bond(&db_tx, &address).await else {
+                    warn!("Rejected liveness report of unknown maker {address}");
+                    continue;
+                };
+                let message = liveness_message(&tracker_address, &address, nonce, timestamp);
+                if let Err(e) = verify_request(&bond, &message, &signature, timestamp) {
+                    warn!("Rejected liveness report of {address}: {e}");
+                    continue;
+                }
+
+                let db_request = DbRequest::ReportLiveness {
+                    address,
+                    bond: bond.outpoint,
+                    nonce,
+                };
+                if let Err(e) = db_tx.send(db_request).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+            }
+            TrackerClientToServer::Offer { .. } => {
+                warn!("Ignoring offer sent outside of a probe");
             }
             TrackerClientToServer::Watch { outpoint } => {
                 info!("Received a watch request from client: {outpoint:?}");
@@ -126,8 +358,133 @@ async fn handle_client(mut stream: TcpStream, db_tx: Sender<DbRequest>) {
                     }
                 }
             }
+            TrackerClientToServer::UpdateAddress {
+                old_address,
+                new_address,
+                nonce,
+                timestamp,
+                signature,
+                proof,
+            } => {
+                info!("Received address update: {old_address} -> {new_address}");
+                if !is_valid_maker_address(&new_address) {
+                    warn!("Rejected malformed new address: {new_address}");
+                    continue;
+                }
+                let Some(bond) = registered_bond(&db_tx, &old_address).await else {
+                    warn!("Rejected address update for unknown maker {old_address}");
+                    continue;
+                };
+                let message = update_address_message(
+                    &tracker_address,
+                    &old_address,
+                    &new_address,
+                    nonce,
+                    timestamp,
+                );
+                if let Err(e) = verify_request(&bond, &message, &signature, timestamp) {
+                    warn!("Rejected address update for {old_address}: {e}");
+                    continue;
+                }
+                let certified = match proof {
+                    Some(proof) => {
+                        let verified = verify_proof_blocking(
+                            &pool,
+                            &rpc,
+                            proof.clone(),
+                            new_address.clone(),
+                            min_bond_confirmations,
+                        )
+                        .await
+                        .and_then(|verified| {
+                            if verified.outpoint != bond.outpoint {
+                                return Err(TrackerError::InvalidBond(format!(
+                                    "proof is for {} instead of {}",
+                                    verified.outpoint, bond.outpoint
+                                )));
+                            }
+                            Ok((verified, proof.certificate()?))
+                        });
+                        match verified {
+                            Ok(certified) => Some(certified),
+                            Err(e) => {
+                                warn!("Rejected address update for {old_address}: {e}");
+                                continue;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+
+                let db_request = DbRequest::UpdateAddress {
+                    old_address,
+                    new_address,
+                    bond: bond.outpoint,
+                    certified,
+                    nonce,
+                };
+                if let Err(e) = db_tx.send(db_request).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+            }
+            TrackerClientToServer::Deregister {
+                address,
+                nonce,
+                timestamp,
+                signature,
+            } => {
+                info!("Received deregistration: {address}");
+                let Some(bond) = registered_bond(&db_tx, &address).await else {
+                    warn!("Rejected deregistration of unknown maker {address}");
+                    continue;
+                };
+                let message = deregister_message(&tracker_address, &address, nonce, timestamp);
+                if let Err(e) = verify_request(&bond, &message, &signature, timestamp) {
+                    warn!("Rejected deregistration of {address}: {e}");
+                    continue;
+                }
+
+                let db_request = DbRequest::Deregister {
+                    address,
+                    bond: bond.outpoint,
+                    nonce,
+                };
+                if let Err(e) = db_tx.send(db_request).await {
+                    error!("Failed to send DB request: {e}");
+                    break;
+                }
+            }
         }
     }
 
     info!("Connection handler exiting.");
 }
+
+/// Runs `verify_proof` on the blocking thread pool, as it queries the
+/// database and may wait on bitcoind.
+async fn verify_proof_blocking(
+    pool: &Arc<Pool<ConnectionManager<SqliteConnection>>>,
+    rpc: &Arc<BitcoinRpc>,
+    proof: FidelityProof,
+    address: String,
+    min_confirmations: u32,
+) -> Result<FidelityBond, TrackerError> {
+    let (pool, rpc) = (pool.clone(), rpc.clone());
+    tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get()?;
+        verify_proof(&mut conn, &rpc, &proof, &address, min_confirmations)
+    })
+    .await
+    .map_err(|e| TrackerError::General(format!("Proof verification failed to run: {e}")))?
+}
+
+/// Bond the maker at `address` registered with, if any.
+async fn registered_bond(db_tx: &Sender<DbRequest>, address: &str) -> Option<FidelityBond> {
+    let (resp_tx, mut resp_rx) = mpsc::channel(1);
+    db_tx
+        .send(DbRequest::Query(address.to_string(), resp_tx))
+        .await
+        .ok()?;
+    resp_rx.recv().await.flatten()?.bond
+}
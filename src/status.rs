@@ -6,6 +6,7 @@ pub enum Sender {
     Mempool(mpsc::Sender<Status>),
     Server(mpsc::Sender<Status>),
     DBManager(mpsc::Sender<Status>),
+    Pruner(mpsc::Sender<Status>),
 }
 
 impl Sender {
@@ -14,6 +15,7 @@ impl Sender {
             Self::Mempool(inner) => inner.send(status).await,
             Self::Server(inner) => inner.send(status).await,
             Self::DBManager(inner) => inner.send(status).await,
+            Self::Pruner(inner) => inner.send(status).await,
         }
     }
 }
@@ -24,6 +26,7 @@ impl Clone for Sender {
             Self::Mempool(inner) => Self::Mempool(inner.clone()),
             Self::Server(inner) => Self::Server(inner.clone()),
             Self::DBManager(inner) => Self::DBManager(inner.clone()),
+            Self::Pruner(inner) => Self::Pruner(inner.clone()),
         }
     }
 }
@@ -33,6 +36,7 @@ pub enum State {
     MempoolShutdown(TrackerError),
     ServerShutdown(TrackerError),
     DBShutdown(TrackerError),
+    PrunerShutdown(TrackerError),
     Healthy(String),
 }
 
@@ -73,6 +77,13 @@ async fn send_status(sender: &Sender, e: TrackerError, outcome: ErrorBranch) ->
             .await
             .unwrap_or(());
         }
+        Sender::Pruner(tx) => {
+            tx.send(Status {
+                state: State::PrunerShutdown(e),
+            })
+            .await
+            .unwrap_or(());
+        }
     }
     outcome
 }
@@ -86,8 +97,12 @@ pub async fn handle_error(sender: &Sender, e: TrackerError) -> ErrorBranch {
         TrackerError::IOError(_) => send_status(sender, e, ErrorBranch::Break).await,
         TrackerError::RPCError(_) => send_status(sender, e, ErrorBranch::Break).await,
         TrackerError::ParsingError => send_status(sender, e, ErrorBranch::Continue).await,
+        TrackerError::InvalidBond(_) => send_status(sender, e, ErrorBranch::Continue).await,
+        TrackerError::InvalidSignature(_) => send_status(sender, e, ErrorBranch::Continue).await,
         TrackerError::SendError => send_status(sender, e, ErrorBranch::Break).await,
         TrackerError::SerdeCbor(_) => send_status(sender, e, ErrorBranch::Break).await,
+        TrackerError::Database(_) => send_status(sender, e, ErrorBranch::Break).await,
+        TrackerError::Pool(_) => send_status(sender, e, ErrorBranch::Break).await,
         TrackerError::General(_) => send_status(sender, e, ErrorBranch::Break).await,
     }
 }
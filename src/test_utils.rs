@@ -0,0 +1,114 @@
+//! Fixtures shared by the unit tests.
+
+use std::sync::Arc;
+
+use bitcoincore_rpc::bitcoin::{
+    Amount, OutPoint, PublicKey, Txid,
+    absolute::LockTime,
+    hashes::Hash,
+    secp256k1::{Message, Secp256k1, SecretKey},
+};
+use chrono::Utc;
+use diesel::{Connection, SqliteConnection, r2d2::ConnectionManager};
+use diesel_migrations::MigrationHarness;
+use r2d2::Pool;
+use tokio::time::Instant;
+
+use crate::{
+    db::policy::Tip,
+    indexer::cert_hash,
+    types::{Certificate, FidelityBond, MakerState, MakerVersion, Offer, ServerInfo},
+};
+
+/// Tip the bonds below are measured against.
+pub(crate) const TIP: Tip = Tip {
+    height: 900_000,
+    median_time: 1_750_000_000,
+};
+
+/// Bond key derived from `seed`.
+pub(crate) fn key(seed: u8) -> SecretKey {
+    SecretKey::from_slice(&[seed; 32]).unwrap()
+}
+
+/// 0.05 BTC bond at output 0 of the transaction with txid `[tag; 32]`, keyed
+/// by `key(1)`, confirmed at 850 000 and locked until 952 560.
+pub(crate) fn bond(tag: u8) -> FidelityBond {
+    FidelityBond {
+        outpoint: OutPoint::new(Txid::from_byte_array([tag; 32]), 0),
+        amount: Amount::from_sat(5_000_000),
+        lock_time: LockTime::from_height(952_560).unwrap(),
+        pubkey: PublicKey::new(key(1).public_key(&Secp256k1::new())),
+        conf_height: Some(850_000),
+        cert_expiry: None,
+    }
+}
+
+/// `bond` with a certificate expiring at period 480 that binds it to
+/// `address`, signed with `key`.
+pub(crate) fn certify(
+    bond: FidelityBond,
+    key: &SecretKey,
+    address: &str,
+) -> (FidelityBond, Certificate) {
+    let bond = FidelityBond {
+        cert_expiry: Some(480),
+        ..bond
+    };
+    let hash = cert_hash(&bond, 480, address);
+    let message = Message::from_digest(hash.to_byte_array());
+    let sig = Secp256k1::new().sign_ecdsa(&message, key);
+    (bond, Certificate { hash, sig })
+}
+
+/// Active maker at `address`, first seen now.
+pub(crate) fn server(address: &str, bond: Option<FidelityBond>) -> ServerInfo {
+    ServerInfo {
+        onion_address: address.to_string(),
+        cooldown: Instant::now(),
+        failures: 0,
+        up_since: None,
+        first_seen: Utc::now(),
+        last_seen: None,
+        bond,
+        certificate: None,
+        state: MakerState::Active,
+    }
+}
+
+/// Offer with the given fee and size limits and fixed relative fees.
+pub(crate) fn offer(base_fee: u64, min_size: u64, max_size: u64) -> Offer {
+    Offer {
+        base_fee,
+        amount_relative_fee_pct: 0.1,
+        time_relative_fee_pct: 0.005,
+        required_confirms: 1,
+        minimum_locktime: 20,
+        max_size,
+        min_size,
+    }
+}
+
+/// Version report of `software` speaking `protocols`.
+pub(crate) fn version(software: &str, protocols: &[u32]) -> MakerVersion {
+    MakerVersion {
+        software: software.to_string(),
+        protocols: protocols.to_vec(),
+    }
+}
+
+/// Empty in-memory database with every migration applied.
+pub(crate) fn memory_db() -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(":memory:").unwrap();
+    conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+    conn
+}
+
+/// Pool over a migrated database in `dir`, set up as in production, for code
+/// that takes connections from a pool.
+pub(crate) fn file_pool(dir: &tempfile::TempDir) -> Arc<Pool<ConnectionManager<SqliteConnection>>> {
+    let url = dir.path().join("tracker.db");
+    let pool = crate::open_pool(url.to_str().unwrap());
+    crate::run_migrations(pool.clone());
+    pool
+}
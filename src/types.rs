@@ -1,17 +1,120 @@
 use bitcoincore_rpc::bitcoin::{
-    Amount, OutPoint, PublicKey, absolute::LockTime, hashes::hash160::Hash,
+    Amount, OutPoint, PublicKey,
+    absolute::LockTime,
+    hashes::{Hash as _, hash160, sha256d::Hash},
     secp256k1::ecdsa::Signature,
 };
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
 use tokio::{sync::mpsc::Sender, time::Instant};
 
-use crate::db::model::MempoolTx;
+use crate::{
+    db::{model::MempoolTx, policy::ListingPolicy, probes::ProbeStats},
+    error::TrackerError,
+};
+
+/// Blocks per difficulty adjustment period, the unit of `cert_expiry`.
+pub(crate) const DIFFICULTY_PERIOD: u64 = 2016;
+
+/// Target block interval, used to turn time-based locktimes into blocks.
+pub(crate) const BLOCK_INTERVAL_SECS: u64 = 600;
 
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
     pub onion_address: String,
     pub cooldown: Instant,
-    pub stale: bool,
+    /// Start of the current run of successful pings. `None` until the
+    /// monitor reaches the maker, and again after a failed ping.
+    pub up_since: Option<Instant>,
+    /// Probes failed in a row since the last answer.
+    pub failures: u32,
+    /// When the tracker first learned of the maker.
+    pub first_seen: DateTime<Utc>,
+    /// Last time the maker answered a ping.
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Bond backing the maker. `None` for makers accepted through the legacy
+    /// announcement heuristic.
+    pub bond: Option<FidelityBond>,
+    /// Certificate from the maker's `Post`. On-chain announcements are signed
+    /// by the bond key themselves and carry none.
+    pub certificate: Option<Certificate>,
+    pub state: MakerState,
+}
+
+/// Where a maker is in its lifecycle. Only `Active` and `Degraded` makers
+/// are handed to takers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MakerState {
+    /// Registered, but has not answered a probe yet.
+    #[default]
+    Pending,
+    /// Answering probes.
+    Active,
+    /// Missed a few probes in a row.
+    Degraded,
+    /// Missed too many probes in a row. Still probed in case it comes back.
+    Stale,
+    /// Silent for so long that it is no longer probed.
+    Evicted,
+    /// Excluded by the operator.
+    Banned,
+    /// The bond output was spent, in the mempool or in a block.
+    Revoked,
+    /// The bond timelock or its certificate has lapsed.
+    Expired,
+    /// Another address registered with the same bond outpoint or pubkey won
+    /// the conflict under the configured `SybilPolicy`.
+    Duplicate,
+}
+
+impl MakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MakerState::Pending => "pending",
+            MakerState::Active => "active",
+            MakerState::Degraded => "degraded",
+            MakerState::Stale => "stale",
+            MakerState::Evicted => "evicted",
+            MakerState::Banned => "banned",
+            MakerState::Revoked => "revoked",
+            MakerState::Expired => "expired",
+            MakerState::Duplicate => "duplicate",
+        }
+    }
+
+    /// Whether makers in this state are handed to takers.
+    pub fn is_listed(&self) -> bool {
+        matches!(self, MakerState::Active | MakerState::Degraded)
+    }
+
+    /// Whether the maker's bond is in good standing, so that its liveness
+    /// alone decides its state and it keeps being probed.
+    pub fn is_probed(&self) -> bool {
+        matches!(
+            self,
+            MakerState::Pending | MakerState::Active | MakerState::Degraded | MakerState::Stale
+        )
+    }
+}
+
+impl FromStr for MakerState {
+    type Err = TrackerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(MakerState::Pending),
+            "active" => Ok(MakerState::Active),
+            "degraded" => Ok(MakerState::Degraded),
+            "stale" => Ok(MakerState::Stale),
+            "evicted" => Ok(MakerState::Evicted),
+            "banned" => Ok(MakerState::Banned),
+            "revoked" => Ok(MakerState::Revoked),
+            "expired" => Ok(MakerState::Expired),
+            "duplicate" => Ok(MakerState::Duplicate),
+            _ => Err(TrackerError::General(format!("Unknown maker state: {s}"))),
+        }
+    }
 }
 
 pub enum DbRequest {
@@ -19,8 +122,110 @@ pub enum DbRequest {
     Query(String, Sender<Option<ServerInfo>>),
     Update(String, ServerInfo),
     QueryAll(Sender<Vec<(String, ServerInfo)>>),
-    QueryActive(Sender<Vec<String>>),
+    /// Outcome of a liveness probe of the maker at `address`: the ping's
+    /// round-trip time, or the kind of error the probe failed with.
+    RecordProbe {
+        address: String,
+        outcome: Result<Duration, String>,
+    },
+    /// Bans the maker at `address`, or lifts its ban. Answers whether the
+    /// maker is registered.
+    Ban {
+        address: String,
+        banned: bool,
+        resp_tx: Sender<bool>,
+    },
+    /// Offer fetched from the maker at `address` during a probe.
+    StoreOffer {
+        address: String,
+        offer: Offer,
+    },
+    /// Cached offers of listed makers that pass the filter.
+    QueryOffers(OfferFilter, Sender<Vec<OfferRecord>>),
+    /// Versions the maker at `address` reported during a probe.
+    StoreVersion {
+        address: String,
+        version: MakerVersion,
+    },
+    /// Reported versions by maker.
+    QueryVersions(Sender<HashMap<String, MakerVersion>>),
+    /// Probe rollups per maker.
+    QueryProbeStats(Sender<HashMap<String, ProbeStats>>),
+    /// Listed makers, only those that reported supporting the protocol
+    /// version if one is given.
+    QueryActive(Option<u32>, Sender<Vec<String>>),
+    /// Listed makers with their bond and ping history.
+    QueryRecords(Sender<Vec<MakerRecord>>),
+    /// Up to `count` listed makers bonded with at least `min_bond`, drawn by
+    /// bond score. A `seed` makes the draw reproducible.
+    QuerySample {
+        count: u32,
+        min_bond: Amount,
+        seed: Option<u64>,
+        resp_tx: Sender<Vec<String>>,
+    },
     WatchUtxo(OutPoint, Sender<Vec<MempoolTx>>),
+    /// Bond outpoints spent by a mempool or confirmed transaction.
+    RevokeBonds(Vec<OutPoint>),
+    /// Bond outpoints whose mempool spends expired without confirming, so
+    /// the makers revoked for them are probed again.
+    ReinstateBonds(Vec<OutPoint>),
+    /// Moves a maker to a new address, keeping its history. Only applied if
+    /// the maker is still backed by `bond` and `nonce` was not seen before.
+    UpdateAddress {
+        old_address: String,
+        new_address: String,
+        bond: OutPoint,
+        /// The verified bond and certificate for `new_address`, required
+        /// for makers registered with a certificate.
+        certified: Option<(FidelityBond, Certificate)>,
+        nonce: u64,
+    },
+    /// Removes a maker. Same preconditions as `UpdateAddress`.
+    Deregister {
+        address: String,
+        bond: OutPoint,
+        nonce: u64,
+    },
+    /// A maker reported on its own that it is alive. Same preconditions as
+    /// `UpdateAddress`.
+    ReportLiveness {
+        address: String,
+        bond: OutPoint,
+        nonce: u64,
+    },
+    /// Most recent registrations that reused another maker's bond.
+    QueryConflicts(Sender<Vec<BondConflict>>),
+    /// Replaces the policy `QueryActive` filters with.
+    SetPolicy(ListingPolicy),
+    /// New chain tip, given as its height and median time past.
+    ExpireBonds {
+        height: u64,
+        median_time: u64,
+    },
+    /// Answered once every request sent before it was handled.
+    Sync(Sender<()>),
+}
+
+/// Change to the maker registry, published by the DB manager so the monitor
+/// does not have to poll for it.
+#[derive(Debug, Clone)]
+pub enum RegistryEvent {
+    Added(String, ServerInfo),
+    /// The maker's state, bond or registration changed.
+    Updated(String, ServerInfo),
+    Removed(String),
+}
+
+/// Two addresses claiming the same bond outpoint or bond pubkey.
+#[derive(Debug, Clone, Serialize)]
+pub struct BondConflict {
+    pub outpoint: OutPoint,
+    /// Address that stays active.
+    pub kept: String,
+    /// Address that was refused or demoted.
+    pub rejected: String,
+    pub at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Hash)]
@@ -37,14 +242,127 @@ pub struct FidelityBond {
     pub(crate) cert_expiry: Option<u32>,
 }
 
+impl FidelityBond {
+    /// Whether the bond can already be swept, or its certificate has lapsed,
+    /// once the chain tip is at `height` with the given median time past.
+    pub(crate) fn is_expired(&self, height: u64, median_time: u64) -> bool {
+        let unlocked = match self.lock_time {
+            LockTime::Blocks(lock_height) => height >= lock_height.to_consensus_u32() as u64,
+            LockTime::Seconds(lock_time) => median_time >= lock_time.to_consensus_u32() as u64,
+        };
+        let cert_expired = self
+            .cert_expiry
+            .is_some_and(|expiry| height >= expiry as u64 * DIFFICULTY_PERIOD);
+        unlocked || cert_expired
+    }
+
+    /// Blocks left until the bond can be swept, estimating time-based locks
+    /// from the median time past.
+    pub(crate) fn remaining_locktime(&self, height: u64, median_time: u64) -> u64 {
+        match self.lock_time {
+            LockTime::Blocks(lock_height) => {
+                (lock_height.to_consensus_u32() as u64).saturating_sub(height)
+            }
+            LockTime::Seconds(lock_time) => {
+                (lock_time.to_consensus_u32() as u64).saturating_sub(median_time)
+                    / BLOCK_INTERVAL_SECS
+            }
+        }
+    }
+}
+
+/// Signature by the bond key over the certificate message binding the bond to
+/// a maker address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Certificate {
+    pub hash: Hash,
+    pub sig: Signature,
+}
+
+/// Certificate hash in a `FidelityProof`.
+///
+/// Makers used to send a 20-byte `hash160` here, which the tracker never
+/// checked. Certificates are now verified, which needs the 32-byte
+/// signed-message hash the signature commits to, so makers must send that
+/// instead. Both are still decoded, so a `Post` from an older maker is
+/// rejected with a reason rather than failing to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertHash {
+    Legacy(hash160::Hash),
+    SignedMessage(Hash),
+}
+
+impl Serialize for CertHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CertHash::Legacy(hash) => hash.serialize(serializer),
+            CertHash::SignedMessage(hash) => hash.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CertHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CertHashVisitor;
+
+        impl de::Visitor<'_> for CertHashVisitor {
+            type Value = CertHash;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a 20 or 32 byte hash")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<CertHash, E> {
+                match v.len() {
+                    20 => hash160::Hash::from_slice(v).map(CertHash::Legacy),
+                    32 => Hash::from_slice(v).map(CertHash::SignedMessage),
+                    len => return Err(E::invalid_length(len, &self)),
+                }
+                .map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<CertHash, E> {
+                match v.len() {
+                    40 => v.parse().map(CertHash::Legacy).map_err(E::custom),
+                    64 => v.parse().map(CertHash::SignedMessage).map_err(E::custom),
+                    len => Err(E::invalid_length(len / 2, &self)),
+                }
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(CertHashVisitor)
+        } else {
+            deserializer.deserialize_bytes(CertHashVisitor)
+        }
+    }
+}
+
 /// Contains proof data related to fidelity bond.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct FidelityProof {
     pub(crate) bond: FidelityBond,
-    pub(crate) cert_hash: Hash,
+    pub(crate) cert_hash: CertHash,
     pub(crate) cert_sig: Signature,
 }
 
+impl FidelityProof {
+    /// The certificate to verify. Fails for the legacy hash format, which
+    /// cannot be checked against the signature.
+    pub(crate) fn certificate(&self) -> Result<Certificate, TrackerError> {
+        match self.cert_hash {
+            CertHash::SignedMessage(hash) => Ok(Certificate {
+                hash,
+                sig: self.cert_sig,
+            }),
+            CertHash::Legacy(_) => Err(TrackerError::InvalidBond(format!(
+                "certificate for {} uses the legacy hash160 format",
+                self.bond.outpoint
+            ))),
+        }
+    }
+}
+
 /// Metadata shared by the maker with the Directory Server for verifying authenticity.
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(private_interfaces)]
@@ -65,18 +383,224 @@ pub enum TrackerClientToServer {
     },
     /// A request sent by the taker to fetch all valid maker addresses from the DNS server.
     Get,
-    /// To gauge server activity
+    /// Like `Get`, but answered with `Records`. Older trackers drop the
+    /// connection, after which the taker can fall back to `Get`.
+    GetRecords,
+    /// Like `Get`, but only for makers that reported supporting
+    /// `protocol_version`.
+    GetCompatible {
+        protocol_version: u32,
+    },
+    /// A request sent by the taker for a random sample of makers, weighted by
+    /// the value of their fidelity bonds.
+    GetSample {
+        /// Number of makers wanted, capped by the server.
+        count: u32,
+        /// Smallest bond a returned maker may have.
+        min_bond: Amount,
+        /// Seed for a reproducible sample. The server draws its own if unset.
+        seed: Option<u64>,
+    },
+    /// A request sent by the taker for the cached offers of listed makers,
+    /// answered with `Offers`.
+    GetOffers {
+        filter: OfferFilter,
+    },
+    /// Answer to `GetOffer`.
+    Offer {
+        offer: Offer,
+    },
+    /// Answer to `Ping`, proving the maker holds its bond key. A maker may
+    /// also send one on its own to report that it is alive, setting `nonce`
+    /// and `timestamp`.
     Pong {
+        /// Address the maker is listed under.
         address: String,
+        /// Bond key signature over the ping's nonce, the tracker's address
+        /// and the maker's address. For a liveness report, over the report's
+        /// nonce and timestamp instead of the ping's nonce. Unset by makers
+        /// predating signed pongs.
+        #[serde(default)]
+        signature: Option<Signature>,
+        #[serde(default)]
+        nonce: Option<u64>,
+        /// Unix time in seconds.
+        #[serde(default)]
+        timestamp: Option<u64>,
+        /// The maker's software and protocol versions, sent when answering
+        /// a ping.
+        #[serde(default)]
+        version: Option<MakerVersion>,
     },
     Watch {
         outpoint: OutPoint,
     },
+    /// Sent by a maker that moved to a new address, signed by its bond key.
+    UpdateAddress {
+        old_address: String,
+        new_address: String,
+        nonce: u64,
+        /// Unix time in seconds.
+        timestamp: u64,
+        signature: Signature,
+        /// Proof for `new_address`, required from makers that registered
+        /// with `Post`: their certificate commits to the old address.
+        #[serde(default)]
+        proof: Option<FidelityProof>,
+    },
+    /// Sent by a retiring maker, signed by its bond key.
+    Deregister {
+        address: String,
+        nonce: u64,
+        /// Unix time in seconds.
+        timestamp: u64,
+        signature: Signature,
+    },
+}
+
+/// What the tracker knows about a listed maker, served in `Records`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MakerRecord {
+    pub address: String,
+    pub bond: Option<BondRecord>,
+    /// Bond value used to weight `GetSample`. Zero without a bond or while
+    /// the chain tip is unknown.
+    pub score: f64,
+    pub first_seen: DateTime<Utc>,
+    /// Last successful ping.
+    pub last_seen: Option<DateTime<Utc>>,
+    pub uptime: Uptime,
+    /// Mean round-trip time of the pings answered in the last 24 hours.
+    pub latency_ms: Option<u64>,
+    /// Latest protocol version the maker speaks, once it reports one.
+    pub protocol_version: Option<u32>,
+    /// Software the maker runs, once it reports it.
+    pub software_version: Option<String>,
+}
+
+/// Share of pings a maker answered over trailing windows. `None` for windows
+/// in which it was not probed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Uptime {
+    pub hour: Option<f64>,
+    pub day: Option<f64>,
+    pub week: Option<f64>,
+}
+
+/// A maker's swap terms, as in the coinswap offer message. Fields of that
+/// message the tracker does not use are ignored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Offer {
+    /// Fee per swap, in sats.
+    pub base_fee: u64,
+    /// Fee in percent of the swapped amount.
+    pub amount_relative_fee_pct: f64,
+    /// Fee in percent of the swapped amount per block of locktime.
+    pub time_relative_fee_pct: f64,
+    pub required_confirms: u32,
+    pub minimum_locktime: u16,
+    /// Largest swap the maker accepts, in sats.
+    pub max_size: u64,
+    /// Smallest swap the maker accepts, in sats.
+    pub min_size: u64,
+}
+
+/// Conditions an offer must meet to be returned by `GetOffers`. Unset
+/// fields do not filter.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OfferFilter {
+    /// Amount the maker must be willing to swap.
+    pub amount: Option<Amount>,
+    pub max_base_fee: Option<Amount>,
+    pub max_amount_relative_fee_pct: Option<f64>,
+    pub max_time_relative_fee_pct: Option<f64>,
+}
+
+impl OfferFilter {
+    pub fn matches(&self, offer: &Offer) -> bool {
+        let size = offer.min_size..=offer.max_size;
+        self.amount
+            .is_none_or(|amount| size.contains(&amount.to_sat()))
+            && self
+                .max_base_fee
+                .is_none_or(|fee| offer.base_fee <= fee.to_sat())
+            && self
+                .max_amount_relative_fee_pct
+                .is_none_or(|pct| offer.amount_relative_fee_pct <= pct)
+            && self
+                .max_time_relative_fee_pct
+                .is_none_or(|pct| offer.time_relative_fee_pct <= pct)
+    }
+}
+
+/// Software a maker runs and the coinswap protocol versions it speaks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MakerVersion {
+    /// Name and version, e.g. `coinswap/0.1.2`.
+    pub software: String,
+    pub protocols: Vec<u32>,
+}
+
+impl MakerVersion {
+    pub fn supports(&self, protocol: u32) -> bool {
+        self.protocols.contains(&protocol)
+    }
+
+    pub fn latest(&self) -> Option<u32> {
+        self.protocols.iter().copied().max()
+    }
+}
+
+/// A listed maker's cached offer, served in `Offers`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OfferRecord {
+    pub address: String,
+    pub offer: Offer,
+    /// When the tracker last fetched the offer.
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BondRecord {
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    pub lock_time: LockTime,
+    pub conf_height: Option<u32>,
+}
+
+impl From<&FidelityBond> for BondRecord {
+    fn from(bond: &FidelityBond) -> Self {
+        Self {
+            outpoint: bond.outpoint,
+            amount: bond.amount,
+            lock_time: bond.lock_time,
+            conf_height: bond.conf_height,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum TrackerServerToClient {
-    Address { addresses: Vec<String> },
-    Ping { address: String, port: u16 },
-    WatchResponse { mempool_tx: Vec<MempoolTx> },
+    Address {
+        addresses: Vec<String>,
+    },
+    Records {
+        records: Vec<MakerRecord>,
+    },
+    Offers {
+        offers: Vec<OfferRecord>,
+    },
+    /// Liveness probe. The maker answers with a signed `Pong`.
+    Ping {
+        address: String,
+        port: u16,
+        /// Random, fresh for every ping.
+        nonce: u64,
+    },
+    /// Asks the maker for its offer after a ping. Makers that do not know the
+    /// request simply have no offer cached.
+    GetOffer,
+    WatchResponse {
+        mempool_tx: Vec<MempoolTx>,
+    },
 }